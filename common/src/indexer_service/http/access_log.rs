@@ -0,0 +1,129 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{str::FromStr, time::Instant};
+
+use axum::{extract::Request, http::HeaderValue, middleware::Next, response::Response};
+use thegraph::types::DeploymentId;
+use tracing::{info, info_span, Instrument};
+use uuid::Uuid;
+
+/// Header identifying a request in the logs. Taken from the client if given,
+/// generated otherwise, and returned in the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest client-provided request ID that is accepted.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Status graph-node answered a forwarded request with, attached to responses
+/// for the access log.
+#[derive(Clone, Copy, Debug)]
+pub struct UpstreamStatus(pub u16);
+
+/// Whether a query response is attestable, attached to responses for the
+/// access log.
+#[derive(Clone, Copy, Debug)]
+pub struct Attestable(pub bool);
+
+/// Run each request in a span carrying its request ID, and log a line
+/// describing it once it has been served.
+pub async fn log_access(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(ToString::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    // Let handlers see the ID that is logged
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        request.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    let span = info_span!("request", %request_id);
+    let start = Instant::now();
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    span.in_scope(|| {
+        info!(
+            %method,
+            path,
+            deployment = deployment(&path).map(tracing::field::display),
+            status = response.status().as_u16(),
+            duration_ms = start.elapsed().as_millis() as u64,
+            upstream_status = response
+                .extensions()
+                .get::<UpstreamStatus>()
+                .map(|status| status.0),
+            attestable = response
+                .extensions()
+                .get::<Attestable>()
+                .map(|attestable| attestable.0),
+            "Request served"
+        )
+    });
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Deployment of a query path such as `/subgraphs/id/Qm...`.
+fn deployment(path: &str) -> Option<DeploymentId> {
+    let (_, id) = path.trim_end_matches('/').rsplit_once("/id/")?;
+    DeploymentId::from_str(id).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn request_id(request: axum::http::Request<Body>) -> String {
+        let router = Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn(log_access));
+        let response = router.oneshot(request).await.unwrap();
+        response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_request_ids() {
+        let request = axum::http::Request::get("/")
+            .header(REQUEST_ID_HEADER, "client-id")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(request_id(request).await, "client-id");
+
+        let request = axum::http::Request::get("/").body(Body::empty()).unwrap();
+        assert!(Uuid::parse_str(&request_id(request).await).is_ok());
+
+        let request = axum::http::Request::get("/")
+            .header(REQUEST_ID_HEADER, "x".repeat(MAX_REQUEST_ID_LENGTH + 1))
+            .body(Body::empty())
+            .unwrap();
+        assert!(Uuid::parse_str(&request_id(request).await).is_ok());
+    }
+
+    #[test]
+    fn test_deployment_of_path() {
+        let id = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        assert_eq!(
+            deployment(&format!("/subgraphs/id/{id}")),
+            Some(DeploymentId::from_str(id).unwrap())
+        );
+        assert_eq!(
+            deployment(&format!("/prefix/subgraphs/id/{id}/")),
+            Some(DeploymentId::from_str(id).unwrap())
+        );
+        assert_eq!(deployment("/status"), None);
+        assert_eq!(deployment("/subgraphs/id/invalid"), None);
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use reqwest::StatusCode;
+
+/// Clients beyond this many are forgotten once their budget is full again.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Limits the bytes of request payload each client may send per second. Each
+/// client can send up to `burst_bytes`, or one second worth of bytes if that
+/// is more, at once.
+pub struct ByteRateLimiter {
+    bytes_per_sec: f64,
+    capacity: f64,
+    budgets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl ByteRateLimiter {
+    /// Bodies larger than `burst_bytes` can never be sent, so it should be at
+    /// least the largest request accepted.
+    pub fn new(bytes_per_sec: u64, burst_bytes: usize) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            capacity: (bytes_per_sec as f64).max(burst_bytes as f64),
+            budgets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take `bytes` from the client's budget, unless that would exceed it.
+    pub fn try_consume(&self, client: IpAddr, bytes: usize) -> bool {
+        let now = Instant::now();
+        let mut budgets = self.budgets.lock().unwrap();
+        if budgets.len() >= MAX_TRACKED_CLIENTS {
+            budgets.retain(|_, (_, updated)| now.duration_since(*updated).as_secs_f64() < 1.0);
+        }
+
+        let (available, updated) = budgets.entry(client).or_insert((self.capacity, now));
+        *available = (*available + now.duration_since(*updated).as_secs_f64() * self.bytes_per_sec)
+            .min(self.capacity);
+        *updated = now;
+
+        let bytes = bytes as f64;
+        if bytes > *available {
+            return false;
+        }
+        *available -= bytes;
+        true
+    }
+}
+
+/// Reject requests with 429 Too Many Requests once their client has sent more
+/// payload bytes than the limiter allows.
+pub async fn limit_byte_rate(
+    State(limiter): State<Arc<ByteRateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    if !limiter.try_consume(client, body.len()) {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            "Query payload byte rate limit exceeded",
+        )
+            .into_response();
+    }
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::post, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn send(router: &Router, client: [u8; 4], bytes: usize) -> StatusCode {
+        let mut request = axum::http::Request::post("/query")
+            .body(Body::from(vec![b'x'; bytes]))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((client, 1234))));
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_byte_rate_limit() {
+        let router = Router::new()
+            .route("/query", post(|| async { "ok" }))
+            .route_layer(middleware::from_fn_with_state(
+                Arc::new(ByteRateLimiter::new(100, 100)),
+                limit_byte_rate,
+            ));
+        let client = [10, 0, 0, 1];
+
+        assert_eq!(send(&router, client, 60).await, StatusCode::OK);
+        assert_eq!(
+            send(&router, client, 60).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(send(&router, client, 30).await, StatusCode::OK);
+
+        // Other clients have their own budget
+        assert_eq!(send(&router, [10, 0, 0, 2], 100).await, StatusCode::OK);
+        assert_eq!(
+            send(&router, [10, 0, 0, 3], 101).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[test]
+    fn test_bodies_above_the_rate_fit_the_burst() {
+        let limiter = ByteRateLimiter::new(10_000, 50_000);
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!(limiter.try_consume(client, 30_000));
+        assert!(!limiter.try_consume(client, 30_000));
+
+        // Waiting long enough lets a body above the per-second rate through
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert!(limiter.try_consume(client, 30_000));
+    }
+
+    #[test]
+    fn test_budget_refills_over_time() {
+        let limiter = ByteRateLimiter::new(1000, 1000);
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert!(limiter.try_consume(client, 1000));
+        assert!(!limiter.try_consume(client, 100));
+
+        std::thread::sleep(std::time::Duration::from_millis(150));
+        assert!(limiter.try_consume(client, 100));
+    }
+}
@@ -28,8 +28,19 @@ pub struct SubgraphConfig {
 pub struct ServerConfig {
     pub host_and_port: SocketAddr,
     pub metrics_host_and_port: SocketAddr,
+    pub process_metrics: bool,
     pub url_prefix: String,
     pub free_query_auth_token: Option<String>,
+    pub readiness_check_attestations: bool,
+    pub strict_trailing_slash: bool,
+    pub error_log_min_interval_secs: Option<u64>,
+    pub global_request_timeout_secs: Option<u64>,
+    pub health_token: Option<String>,
+    pub enable_root_info: bool,
+    pub build_header: bool,
+    pub byte_rate_limit_per_client: Option<u64>,
+    pub max_request_bytes: usize,
+    pub shutdown_grace_secs: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
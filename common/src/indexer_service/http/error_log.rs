@@ -0,0 +1,173 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// Don't let the map of recent errors grow beyond this many distinct messages
+const MAX_TRACKED_ERRORS: usize = 1000;
+
+static ERROR_LOG_LIMITER: OnceLock<ErrorLogLimiter> = OnceLock::new();
+
+/// Limits how often identical error messages are logged.
+pub struct ErrorLogLimiter {
+    min_interval: Duration,
+    /// When each message was last logged and how often it was suppressed since
+    recent: Mutex<HashMap<String, (Instant, u64)>>,
+}
+
+impl ErrorLogLimiter {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            recent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether to log the message, with the number of times it was suppressed
+    /// since it was last logged.
+    pub fn check(&self, message: &str) -> Option<u64> {
+        let now = Instant::now();
+        let mut recent = self.recent.lock().unwrap();
+        if let Some((logged_at, suppressed)) = recent.get_mut(message) {
+            if now.duration_since(*logged_at) < self.min_interval {
+                *suppressed += 1;
+                return None;
+            }
+            let suppressed = std::mem::take(suppressed);
+            *logged_at = now;
+            return Some(suppressed);
+        }
+
+        let evicted = if recent.len() >= MAX_TRACKED_ERRORS {
+            self.take_expired(&mut recent, now)
+        } else {
+            vec![]
+        };
+        recent.insert(message.to_string(), (now, 0));
+        drop(recent);
+
+        log_suppressed(evicted);
+        Some(0)
+    }
+
+    /// Forget messages whose interval has passed, returning those that were
+    /// suppressed in the meantime with their counts, so they can be reported
+    /// even if the error never occurs again.
+    pub fn flush(&self) -> Vec<(String, u64)> {
+        let mut recent = self.recent.lock().unwrap();
+        self.take_expired(&mut recent, Instant::now())
+    }
+
+    fn take_expired(
+        &self,
+        recent: &mut HashMap<String, (Instant, u64)>,
+        now: Instant,
+    ) -> Vec<(String, u64)> {
+        let mut expired = vec![];
+        recent.retain(|message, (logged_at, suppressed)| {
+            if now.duration_since(*logged_at) < self.min_interval {
+                return true;
+            }
+            if *suppressed > 0 {
+                expired.push((message.clone(), *suppressed));
+            }
+            false
+        });
+        expired
+    }
+}
+
+fn log_suppressed(summaries: Vec<(String, u64)>) {
+    for (e, suppressed) in summaries {
+        tracing::error!(
+            %e,
+            suppressed,
+            "An IndexerServiceError occoured (suppressed {suppressed} identical errors)."
+        );
+    }
+}
+
+/// Rate limit error logs to at most one per `min_interval` for identical errors.
+///
+/// Suppressed counts are flushed every `min_interval`, so they are not lost when
+/// an error stops occurring.
+pub fn init(min_interval: Duration) {
+    if ERROR_LOG_LIMITER
+        .set(ErrorLogLimiter::new(min_interval))
+        .is_err()
+    {
+        return;
+    }
+    tokio::spawn(async move {
+        // `interval` panics on a zero period
+        let mut interval = tokio::time::interval(min_interval.max(Duration::from_secs(1)));
+        loop {
+            interval.tick().await;
+            if let Some(limiter) = ERROR_LOG_LIMITER.get() {
+                log_suppressed(limiter.flush());
+            }
+        }
+    });
+}
+
+pub fn log_error(e: &impl Display) {
+    let Some(limiter) = ERROR_LOG_LIMITER.get() else {
+        tracing::error!(%e, "An IndexerServiceError occoured.");
+        return;
+    };
+    match limiter.check(&e.to_string()) {
+        Some(0) => tracing::error!(%e, "An IndexerServiceError occoured."),
+        Some(suppressed) => tracing::error!(
+            %e,
+            suppressed,
+            "An IndexerServiceError occoured (suppressed {suppressed} identical errors)."
+        ),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_errors_are_logged_once_per_interval() {
+        let limiter = ErrorLogLimiter::new(Duration::from_millis(100));
+
+        assert_eq!(limiter.check("connection refused"), Some(0));
+        for _ in 0..3 {
+            assert_eq!(limiter.check("connection refused"), None);
+        }
+        // Other errors are not affected
+        assert_eq!(limiter.check("timed out"), Some(0));
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(limiter.check("connection refused"), Some(3));
+        assert_eq!(limiter.check("connection refused"), None);
+    }
+
+    #[test]
+    fn test_suppressed_counts_are_flushed_without_another_occurrence() {
+        let limiter = ErrorLogLimiter::new(Duration::from_millis(100));
+
+        assert_eq!(limiter.check("connection refused"), Some(0));
+        assert_eq!(limiter.check("connection refused"), None);
+        assert_eq!(limiter.check("connection refused"), None);
+        assert_eq!(limiter.check("timed out"), Some(0));
+
+        // Nothing to report while the interval is still running
+        assert!(limiter.flush().is_empty());
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(limiter.flush(), vec![("connection refused".to_string(), 2)]);
+        assert!(limiter.flush().is_empty());
+
+        // The message was forgotten, so the next occurrence is logged right away
+        assert_eq!(limiter.check("connection refused"), Some(0));
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    future::IntoFuture,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::access_log::REQUEST_ID_HEADER;
+
+/// Requests currently being served, so that the ones cut off by a shutdown
+/// can be logged.
+#[derive(Clone, Default)]
+pub struct InFlightRequests {
+    requests: Arc<Mutex<HashMap<u64, InFlightRequest>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+struct InFlightRequest {
+    method: Method,
+    path: String,
+    request_id: Option<String>,
+    started: Instant,
+}
+
+/// Removes a request from the in-flight requests once it has been served, or
+/// dropped.
+struct InFlightGuard {
+    in_flight: InFlightRequests,
+    id: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.requests.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl InFlightRequests {
+    fn insert(&self, request: &Request) -> InFlightGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.requests.lock().unwrap().insert(
+            id,
+            InFlightRequest {
+                method: request.method().clone(),
+                path: request.uri().path().to_string(),
+                request_id: request
+                    .headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToString::to_string),
+                started: Instant::now(),
+            },
+        );
+        InFlightGuard {
+            in_flight: self.clone(),
+            id,
+        }
+    }
+
+    fn log_abandoned(&self) {
+        for request in self.requests.lock().unwrap().values() {
+            warn!(
+                method = %request.method,
+                path = request.path,
+                request_id = request.request_id,
+                duration_ms = request.started.elapsed().as_millis() as u64,
+                "Request abandoned on shutdown"
+            );
+        }
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+/// Keep track of the requests being served.
+pub async fn track_in_flight(
+    State(in_flight): State<InFlightRequests>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let _guard = in_flight.insert(&request);
+    next.run(request).await
+}
+
+/// Run a server that shuts down gracefully once `shutdown` is cancelled, giving
+/// in-flight requests `grace` to finish. Requests still running by then are
+/// logged and abandoned.
+pub async fn serve_with_grace<F>(
+    server: F,
+    shutdown: CancellationToken,
+    grace: Duration,
+    in_flight: &InFlightRequests,
+) -> io::Result<()>
+where
+    F: IntoFuture<Output = io::Result<()>>,
+{
+    let server = server.into_future();
+    tokio::pin!(server);
+    tokio::select! {
+        result = &mut server => return result,
+        _ = shutdown.cancelled() => {}
+    }
+
+    info!(?grace, "Waiting for in-flight requests to finish");
+    match tokio::time::timeout(grace, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            in_flight.log_abandoned();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware, routing::get, serve, Router};
+    use tokio::{net::TcpListener, task::JoinHandle};
+
+    use super::*;
+
+    /// Serve a route that takes `delay` to answer, and request it once.
+    async fn serve_slow_request(
+        delay: Duration,
+        grace: Duration,
+    ) -> (
+        io::Result<()>,
+        JoinHandle<Result<reqwest::Response, reqwest::Error>>,
+        InFlightRequests,
+    ) {
+        let in_flight = InFlightRequests::default();
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(move || async move {
+                    tokio::time::sleep(delay).await;
+                    "done"
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                in_flight.clone(),
+                track_in_flight,
+            ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/slow", listener.local_addr().unwrap());
+
+        let shutdown = CancellationToken::new();
+        let signal = shutdown.clone();
+        let server = serve(listener, router).with_graceful_shutdown(async move {
+            signal.cancelled().await;
+        });
+        let request = tokio::spawn(reqwest::get(url));
+
+        // Shut down while the request is being served
+        let signalled = tokio::spawn({
+            let shutdown = shutdown.clone();
+            let in_flight = in_flight.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                assert_eq!(in_flight.len(), 1);
+                shutdown.cancel();
+            }
+        });
+
+        let result = serve_with_grace(server, shutdown, grace, &in_flight).await;
+        signalled.await.unwrap();
+        (result, request, in_flight)
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_requests_are_drained() {
+        let (result, request, in_flight) =
+            serve_slow_request(Duration::from_millis(100), Duration::from_secs(5)).await;
+        assert!(result.is_ok());
+        let response = request.await.unwrap().unwrap();
+        assert_eq!(response.text().await.unwrap(), "done");
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_abandoned_after_grace_period() {
+        let (result, request, in_flight) = tokio::time::timeout(
+            Duration::from_secs(5),
+            serve_slow_request(Duration::from_secs(60), Duration::from_millis(100)),
+        )
+        .await
+        .expect("shutdown should not wait for the request");
+        assert!(result.is_ok());
+        // The abandoned request was still running
+        assert_eq!(in_flight.len(), 1);
+        request.abort();
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use reqwest::StatusCode;
+
+/// Only let requests with the bearer token through to the health routes.
+pub async fn require_health_token(
+    State(token): State<Arc<String>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| value == token.as_str());
+
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn health_routes(token: Option<&str>) -> Router {
+        let router = Router::new()
+            .route("/livez", get(|| async { "alive" }))
+            .route("/readyz", get(|| async { "ready" }));
+        match token {
+            Some(token) => router.route_layer(middleware::from_fn_with_state(
+                Arc::new(token.to_string()),
+                require_health_token,
+            )),
+            None => router,
+        }
+    }
+
+    async fn get_readyz(router: Router, authorization: Option<&str>) -> StatusCode {
+        get_path(router, "/readyz", authorization).await
+    }
+
+    async fn get_path(router: Router, path: &str, authorization: Option<&str>) -> StatusCode {
+        let mut request = axum::http::Request::get(path);
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_health_routes_without_token() {
+        assert_eq!(get_readyz(health_routes(None), None).await, StatusCode::OK);
+        assert_eq!(
+            get_readyz(health_routes(None), Some("Bearer anything")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_routes_with_token() {
+        let router = health_routes(Some("secret"));
+        assert_eq!(
+            get_readyz(router.clone(), None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get_readyz(router.clone(), Some("Bearer wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get_readyz(router, Some("Bearer secret")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_livez_requires_token() {
+        assert_eq!(
+            get_path(health_routes(None), "/livez", None).await,
+            StatusCode::OK
+        );
+
+        let router = health_routes(Some("secret"));
+        assert_eq!(
+            get_path(router.clone(), "/livez", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get_path(router, "/livez", Some("Bearer secret")).await,
+            StatusCode::OK
+        );
+    }
+}
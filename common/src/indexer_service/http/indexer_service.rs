@@ -8,17 +8,20 @@ use std::{
 
 use alloy_sol_types::eip712_domain;
 use anyhow;
-use autometrics::prometheus_exporter;
-use axum::extract::MatchedPath;
-use axum::http::{Method, Request};
+use axum::body::Body;
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::{DefaultBodyLimit, MatchedPath, State};
+use axum::http::{header::CONTENT_LENGTH, HeaderMap, HeaderName, HeaderValue, Method, Request};
+use axum::middleware::{self, Next};
 use axum::serve;
+use axum::ServiceExt;
 use axum::{
     async_trait,
     response::{IntoResponse, Response},
     routing::{get, post},
     Extension, Json, Router,
 };
-use build_info::BuildInfo;
+use build_info::{BuildInfo, VersionControl};
 use eventuals::Eventual;
 use reqwest::StatusCode;
 use serde::{de::DeserializeOwned, Serialize};
@@ -29,16 +32,27 @@ use thegraph::types::{Attestation, DeploymentId};
 use thiserror::Error;
 use tokio::net::TcpListener;
 use tokio::signal;
+use tokio_util::sync::CancellationToken;
+use tower::{timeout::TimeoutLayer, BoxError, Layer, ServiceBuilder};
 use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 use tower_http::cors;
 use tower_http::cors::CorsLayer;
+use tower_http::normalize_path::NormalizePathLayer;
+use tower_http::set_header::SetResponseHeaderLayer;
 use tower_http::trace::TraceLayer;
 use tracing::{info, info_span};
 
 use crate::{
     address::public_key,
     indexer_service::http::{
-        metrics::IndexerServiceMetrics, static_subgraph::static_subgraph_request_handler,
+        access_log::log_access,
+        byte_rate_limit::{limit_byte_rate, ByteRateLimiter},
+        error_log,
+        graceful_shutdown::{serve_with_grace, track_in_flight, InFlightRequests},
+        health_token::require_health_token,
+        metrics::{metrics_handler, register_process_metrics, IndexerServiceMetrics},
+        readiness::readiness_handler,
+        static_subgraph::static_subgraph_request_handler,
     },
     prelude::{
         attestation_signers, dispute_manager, escrow_accounts, indexer_allocations,
@@ -60,7 +74,7 @@ pub trait IndexerServiceResponse {
 
 #[async_trait]
 pub trait IndexerServiceImpl {
-    type Error: std::error::Error;
+    type Error: std::error::Error + IntoResponse;
     type Request: DeserializeOwned + Send + Debug + Serialize;
     type Response: IndexerServiceResponse + Sized;
     type State: Send + Sync;
@@ -69,6 +83,7 @@ pub trait IndexerServiceImpl {
         &self,
         manifest_id: DeploymentId,
         request: Self::Request,
+        headers: HeaderMap,
     ) -> Result<(Self::Request, Self::Response), Self::Error>;
 }
 
@@ -87,6 +102,10 @@ where
     NoSignerForManifest(DeploymentId),
     #[error("Invalid request body: {0}")]
     InvalidRequest(anyhow::Error),
+    #[error("Request body is not valid JSON: {0}")]
+    MalformedJson(serde_json::Error),
+    #[error("Request body is larger than {0} bytes")]
+    RequestTooLarge(usize),
     #[error("Error while processing the request: {0}")]
     ProcessingError(E),
     #[error("No valid receipt or free query auth token provided")]
@@ -97,22 +116,45 @@ where
     FailedToSignAttestation,
     #[error("Failed to query subgraph: {0}")]
     FailedToQueryStaticSubgraph(anyhow::Error),
+    #[error("Attestation signing is not available: {0}")]
+    AttestationSignerMisconfigured(anyhow::Error),
 }
 
 impl<E> IntoResponse for IndexerServiceError<E>
 where
-    E: std::error::Error,
+    E: std::error::Error + IntoResponse,
 {
     fn into_response(self) -> Response {
         use IndexerServiceError::*;
 
+        // Processing errors know best how to present themselves
+        if let ProcessingError(e) = self {
+            error_log::log_error(&e);
+            return e.into_response();
+        }
+
+        // Clients of the query routes expect GraphQL errors
+        let graphql_status = match self {
+            MalformedJson(_) => Some(StatusCode::BAD_REQUEST),
+            RequestTooLarge(_) => Some(StatusCode::PAYLOAD_TOO_LARGE),
+            _ => None,
+        };
+        if let Some(status) = graphql_status {
+            error_log::log_error(&self);
+            return (
+                status,
+                Json(serde_json::json!({ "errors": [{ "message": self.to_string() }] })),
+            )
+                .into_response();
+        }
+
         #[derive(Serialize)]
         struct ErrorResponse {
             message: String,
         }
 
         let status = match self {
-            ServiceNotReady => StatusCode::SERVICE_UNAVAILABLE,
+            ServiceNotReady | AttestationSignerMisconfigured(_) => StatusCode::SERVICE_UNAVAILABLE,
 
             Unauthorized => StatusCode::UNAUTHORIZED,
 
@@ -120,14 +162,17 @@ where
                 StatusCode::INTERNAL_SERVER_ERROR
             }
 
-            ReceiptError(_)
-            | InvalidRequest(_)
-            | InvalidFreeQueryAuthToken
-            | ProcessingError(_) => StatusCode::BAD_REQUEST,
+            ReceiptError(_) | InvalidRequest(_) | InvalidFreeQueryAuthToken => {
+                StatusCode::BAD_REQUEST
+            }
+
+            ProcessingError(_) | MalformedJson(_) | RequestTooLarge(_) => {
+                unreachable!("processing and GraphQL errors are handled above")
+            }
 
             FailedToQueryStaticSubgraph(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
-        tracing::error!(%self, "An IndexerServiceError occoured.");
+        error_log::log_error(&self);
         (
             status,
             Json(ErrorResponse {
@@ -142,6 +187,19 @@ where
 pub struct IndexerServiceRelease {
     version: String,
     dependencies: HashMap<String, String>,
+    #[serde(skip)]
+    commit: Option<String>,
+}
+
+impl IndexerServiceRelease {
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// Git commit the service was built from, if known.
+    pub fn commit(&self) -> Option<&str> {
+        self.commit.as_deref()
+    }
 }
 
 impl From<&BuildInfo> for IndexerServiceRelease {
@@ -155,10 +213,17 @@ impl From<&BuildInfo> for IndexerServiceRelease {
                     .iter()
                     .map(|d| (d.name.clone(), d.version.to_string())),
             ),
+            commit: match &value.version_control {
+                Some(VersionControl::Git(git)) => Some(git.commit_id.clone()),
+                _ => None,
+            },
         }
     }
 }
 
+/// Response header carrying the commit the service was built from.
+pub const BUILD_HEADER: &str = "x-indexer-build";
+
 pub struct IndexerServiceOptions<I>
 where
     I: IndexerServiceImpl + Sync + Send + 'static,
@@ -191,6 +256,10 @@ impl IndexerService {
     {
         let metrics = IndexerServiceMetrics::new(options.metrics_prefix);
 
+        if let Some(secs) = options.config.server.error_log_min_interval_secs {
+            error_log::init(Duration::from_secs(secs));
+        }
+
         let http_client = reqwest::Client::builder()
             .tcp_nodelay(true)
             .timeout(Duration::from_secs(30))
@@ -328,10 +397,32 @@ impl IndexerService {
             serde_json::json!({ "publicKey": public_key(&options.config.indexer.operator_mnemonic)?}),
         );
 
+        let build_commit = options
+            .config
+            .server
+            .build_header
+            .then(|| options.release.commit.clone())
+            .flatten();
+        let root = if options.config.server.enable_root_info {
+            get(Json(root_info(&options.release, options.url_namespace)))
+        } else {
+            get("Service is up and running")
+        };
+        let mut health_routes = Router::new()
+            .route("/livez", get("Service is alive"))
+            .route("/readyz", get(readiness_handler::<I>));
+        if let Some(token) = options.config.server.health_token.clone() {
+            health_routes = health_routes.route_layer(middleware::from_fn_with_state(
+                Arc::new(token),
+                require_health_token,
+            ));
+        }
+
         let mut misc_routes = Router::new()
-            .route("/", get("Service is up and running"))
+            .route("/", root)
             .route("/version", get(Json(options.release)))
             .route("/info", get(operator_address))
+            .merge(health_routes)
             .layer(misc_rate_limiter);
 
         // Rate limits by allowing bursts of 50 requests and requiring 20ms of
@@ -375,24 +466,49 @@ impl IndexerService {
 
         misc_routes = misc_routes.with_state(state.clone());
 
-        let data_routes = Router::new()
-            .route(
-                PathBuf::from(options.config.server.url_prefix)
-                    .join(format!("{}/id/:id", options.url_namespace))
-                    .to_str()
-                    .expect("Failed to set up `/{url_namespace}/id/:id` route"),
-                post(request_handler::<I>),
-            )
-            .with_state(state.clone());
+        let mut data_routes = Router::new().route(
+            PathBuf::from(options.config.server.url_prefix)
+                .join(format!("{}/id/:id", options.url_namespace))
+                .to_str()
+                .expect("Failed to set up `/{url_namespace}/id/:id` route"),
+            post(request_handler::<I>),
+        );
+        if let Some(bytes_per_sec) = options.config.server.byte_rate_limit_per_client {
+            data_routes = data_routes.route_layer(middleware::from_fn_with_state(
+                // Any request within the size limit can get through eventually
+                Arc::new(ByteRateLimiter::new(
+                    bytes_per_sec,
+                    options.config.server.max_request_bytes,
+                )),
+                limit_byte_rate,
+            ));
+        }
+        let data_routes = data_routes.with_state(state.clone());
 
-        let router = misc_routes
-            .merge(data_routes)
-            .merge(options.extra_routes)
+        let router = with_request_size_limit::<I::Error, _>(
+            misc_routes.merge(data_routes).merge(options.extra_routes),
+            options.config.server.max_request_bytes,
+        );
+        let router = with_request_timeout(
+            router,
+            options
+                .config
+                .server
+                .global_request_timeout_secs
+                .map(Duration::from_secs),
+        );
+        let in_flight = InFlightRequests::default();
+        let router = with_build_header(router, build_commit.as_deref())
+            .layer(middleware::from_fn_with_state(
+                in_flight.clone(),
+                track_in_flight,
+            ))
+            .layer(middleware::from_fn(log_access))
             .layer(
                 CorsLayer::new()
                     .allow_origin(cors::Any)
                     .allow_headers(cors::Any)
-                    .allow_methods([Method::OPTIONS, Method::POST, Method::GET]),
+                    .allow_methods([Method::OPTIONS, Method::POST, Method::GET, Method::HEAD]),
             )
             .layer(
                 TraceLayer::new_for_http()
@@ -420,6 +536,9 @@ impl IndexerService {
             )
             .with_state(state);
 
+        if options.config.server.process_metrics {
+            register_process_metrics();
+        }
         Self::serve_metrics(options.config.server.metrics_host_and_port);
 
         info!(
@@ -430,11 +549,51 @@ impl IndexerService {
             .await
             .expect("Failed to bind to indexer-service port");
 
-        Ok(serve(
-            listener,
-            router.into_make_service_with_connect_info::<SocketAddr>(),
+        // Stop accepting connections on shutdown, but give in-flight requests
+        // some time to finish
+        let shutdown = CancellationToken::new();
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                shutdown_signal().await;
+                shutdown.cancel();
+            }
+        });
+        let grace = Duration::from_secs(options.config.server.shutdown_grace_secs);
+        let signal = {
+            let shutdown = shutdown.clone();
+            async move { shutdown.cancelled().await }
+        };
+
+        if options.config.server.strict_trailing_slash {
+            return Ok(serve_with_grace(
+                serve(
+                    listener,
+                    router.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(signal),
+                shutdown,
+                grace,
+                &in_flight,
+            )
+            .await?);
+        }
+
+        // Middleware added to the router only runs after routing, so the path
+        // has to be normalized outside of it
+        let router = NormalizePathLayer::trim_trailing_slash().layer(router);
+        Ok(serve_with_grace(
+            serve(
+                listener,
+                ServiceExt::<axum::extract::Request>::into_make_service_with_connect_info::<
+                    SocketAddr,
+                >(router),
+            )
+            .with_graceful_shutdown(signal),
+            shutdown,
+            grace,
+            &in_flight,
         )
-        .with_graceful_shutdown(shutdown_signal())
         .await?)
     }
 
@@ -442,10 +601,7 @@ impl IndexerService {
         info!(address = %host_and_port, "Serving prometheus metrics");
 
         tokio::spawn(async move {
-            let router = Router::new().route(
-                "/metrics",
-                get(|| async { prometheus_exporter::encode_http_response() }),
-            );
+            let router = Router::new().route("/metrics", get(metrics_handler));
 
             serve(
                 TcpListener::bind(host_and_port)
@@ -459,6 +615,93 @@ impl IndexerService {
     }
 }
 
+/// Greeting served on `/` that points clients to the other routes.
+fn root_info(release: &IndexerServiceRelease, url_namespace: &str) -> serde_json::Value {
+    serde_json::json!({
+        "message": "Service is up and running",
+        "version": release.version,
+        "links": {
+            "version": "/version",
+            "info": "/info",
+            "health": "/readyz",
+            "queries": format!("/{url_namespace}/id/:id"),
+        }
+    })
+}
+
+/// Fail requests that take longer than `timeout` with 504 Gateway Timeout.
+fn with_request_timeout<S>(router: Router<S>, timeout: Option<Duration>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let Some(timeout) = timeout else {
+        return router;
+    };
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(|_: BoxError| async {
+                StatusCode::GATEWAY_TIMEOUT
+            }))
+            .layer(TimeoutLayer::new(timeout)),
+    )
+}
+
+/// Fail requests with bodies larger than `max_bytes` with 413 Payload Too Large.
+fn with_request_size_limit<E, S>(router: Router<S>, max_bytes: usize) -> Router<S>
+where
+    E: Error + IntoResponse + Send + 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(middleware::from_fn_with_state(
+            max_bytes,
+            reject_large_requests::<E>,
+        ))
+        // Bodies have been checked already, don't apply the default limit
+        .layer(DefaultBodyLimit::max(max_bytes))
+}
+
+async fn reject_large_requests<E>(
+    State(max_bytes): State<usize>,
+    request: Request<Body>,
+    next: Next,
+) -> Response
+where
+    E: Error + IntoResponse,
+{
+    let too_large = || IndexerServiceError::<E>::RequestTooLarge(max_bytes).into_response();
+
+    // Don't bother reading bodies that announce being too large
+    let content_length = request
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if content_length.is_some_and(|length| length > max_bytes) {
+        return too_large();
+    }
+
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, max_bytes).await else {
+        return too_large();
+    };
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// Report the commit the service was built from on all responses.
+fn with_build_header<S>(router: Router<S>, commit: Option<&str>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    match commit.and_then(|commit| HeaderValue::from_str(commit).ok()) {
+        Some(commit) => router.layer(SetResponseHeaderLayer::overriding(
+            HeaderName::from_static(BUILD_HEADER),
+            commit,
+        )),
+        None => router,
+    }
+}
+
 pub async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -480,3 +723,167 @@ pub async fn shutdown_signal() {
 
     info!("Signal received, starting graceful shutdown");
 }
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trailing_slash_is_ignored() {
+        let router = Router::new().route("/status", post(|| async { "status" }));
+        let router = NormalizePathLayer::trim_trailing_slash().layer(router);
+
+        for path in ["/status", "/status/"] {
+            let response = router
+                .clone()
+                .oneshot(Request::post(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_header() {
+        let router = Router::new().route("/", get(|| async { "ok" }));
+
+        let response = with_build_header(router.clone(), Some("3f1c2a9"))
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[BUILD_HEADER], "3f1c2a9");
+
+        let response = with_build_header(router, None)
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(response.headers().get(BUILD_HEADER).is_none());
+    }
+
+    #[test]
+    fn test_root_info() {
+        let release = IndexerServiceRelease {
+            version: "1.2.3".to_string(),
+            dependencies: HashMap::new(),
+            commit: None,
+        };
+        assert_eq!(
+            root_info(&release, "subgraphs"),
+            serde_json::json!({
+                "message": "Service is up and running",
+                "version": "1.2.3",
+                "links": {
+                    "version": "/version",
+                    "info": "/info",
+                    "health": "/readyz",
+                    "queries": "/subgraphs/id/:id",
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_error_envelope() {
+        #[derive(Debug, thiserror::Error)]
+        #[error("processing failed")]
+        struct ProcessingError;
+
+        impl IntoResponse for ProcessingError {
+            fn into_response(self) -> Response {
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+
+        let error = serde_json::from_slice::<serde_json::Value>(b"{\"query\": ").unwrap_err();
+        let response = IndexerServiceError::<ProcessingError>::MalformedJson(error).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let errors = body["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Request body is not valid JSON"));
+    }
+
+    #[tokio::test]
+    async fn test_request_size_limit() {
+        let router = Router::new()
+            .route("/status", post(|| async { "status" }))
+            .route("/cost", post(|body: String| async move { body }));
+        let router = with_request_size_limit::<std::convert::Infallible, _>(router, 16);
+
+        for path in ["/status", "/cost"] {
+            let response = router
+                .clone()
+                .oneshot(Request::post(path).body(Body::from("{ a }")).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+
+            let response = router
+                .clone()
+                .oneshot(
+                    Request::post(path)
+                        .body(Body::from("x".repeat(17)))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE, "{path}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body["errors"][0]["message"],
+                "Request body is larger than 16 bytes"
+            );
+        }
+
+        // Bodies are checked even when they lie about their length
+        let response = router
+            .oneshot(
+                Request::post("/cost")
+                    .header(CONTENT_LENGTH, "4")
+                    .body(Body::from("x".repeat(17)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_global_request_timeout() {
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "done"
+                }),
+            )
+            .route("/fast", get(|| async { "done" }));
+        let router = with_request_timeout(router, Some(Duration::from_millis(50)));
+
+        let response = router
+            .clone()
+            .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let response = router
+            .oneshot(Request::get("/fast").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
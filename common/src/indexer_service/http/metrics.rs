@@ -1,7 +1,10 @@
 // Copyright 2023-, GraphOps and Semiotic Labs.
 // SPDX-License-Identifier: Apache-2.0
 
-use prometheus::{register_int_counter_vec, IntCounterVec};
+use autometrics::prometheus_exporter;
+use axum::http::StatusCode;
+use prometheus::{core::Collector, opts, IntCounterVec, TextEncoder};
+use tracing::warn;
 
 pub struct IndexerServiceMetrics {
     pub requests: IntCounterVec,
@@ -10,28 +13,123 @@ pub struct IndexerServiceMetrics {
 }
 
 impl IndexerServiceMetrics {
+    /// Metrics that fail to register, e.g. because metrics with the same name
+    /// exist already, are still recorded but not exported.
     pub fn new(prefix: &str) -> Self {
         IndexerServiceMetrics {
-            requests: register_int_counter_vec!(
-                format!("{prefix}_service_requests_total"),
-                "Incoming requests",
-                &["manifest"]
-            )
-            .unwrap(),
-
-            successful_requests: register_int_counter_vec!(
-                format!("{prefix}_service_requests_ok"),
-                "Successfully executed requests",
-                &["manifest"]
-            )
-            .unwrap(),
-
-            failed_requests: register_int_counter_vec!(
-                format!("{prefix}_service_requests_failed"),
-                "requests that failed to execute",
-                &["manifest"]
-            )
-            .unwrap(),
+            requests: register_or_warn(
+                IntCounterVec::new(
+                    opts!(
+                        format!("{prefix}_service_requests_total"),
+                        "Incoming requests"
+                    ),
+                    &["manifest"],
+                )
+                .expect("Valid metric name"),
+            ),
+
+            successful_requests: register_or_warn(
+                IntCounterVec::new(
+                    opts!(
+                        format!("{prefix}_service_requests_ok"),
+                        "Successfully executed requests"
+                    ),
+                    &["manifest"],
+                )
+                .expect("Valid metric name"),
+            ),
+
+            failed_requests: register_or_warn(
+                IntCounterVec::new(
+                    opts!(
+                        format!("{prefix}_service_requests_failed"),
+                        "requests that failed to execute"
+                    ),
+                    &["manifest"],
+                )
+                .expect("Valid metric name"),
+            ),
         }
     }
 }
+
+/// Register a metric in the default registry, only warning if that fails.
+pub fn register_or_warn<C: Collector + Clone + 'static>(metric: C) -> C {
+    if let Err(e) = prometheus::register(Box::new(metric.clone())) {
+        let names: Vec<_> = metric
+            .desc()
+            .iter()
+            .map(|desc| desc.fq_name.clone())
+            .collect();
+        warn!(error = %e, ?names, "Failed to register metrics, continuing without them");
+    }
+    metric
+}
+
+/// Register CPU, memory and open file descriptor metrics of this process
+/// (`process_*`). These are only available on Linux.
+pub fn register_process_metrics() {
+    #[cfg(target_os = "linux")]
+    {
+        let collector = prometheus::process_collector::ProcessCollector::for_self();
+        match prometheus::register(Box::new(collector)) {
+            Ok(()) | Err(prometheus::Error::AlreadyReg) => {}
+            Err(e) => warn!(error = %e, "Failed to register process metrics"),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    warn!("Process metrics are only available on Linux");
+}
+
+/// Serve the metrics recorded through autometrics together with those in the
+/// default prometheus registry.
+pub async fn metrics_handler() -> Result<String, (StatusCode, String)> {
+    let internal_error = |e: String| {
+        warn!(error = %e, "Failed to encode metrics");
+        (StatusCode::INTERNAL_SERVER_ERROR, e)
+    };
+    let mut metrics =
+        prometheus_exporter::encode_to_string().map_err(|e| internal_error(e.to_string()))?;
+    TextEncoder::new()
+        .encode_utf8(&prometheus::gather(), &mut metrics)
+        .map_err(|e| internal_error(e.to_string()))?;
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    #[test]
+    fn test_duplicate_registration_is_not_fatal() {
+        let metrics = IndexerServiceMetrics::new("duplicate_test");
+        let duplicate = IndexerServiceMetrics::new("duplicate_test");
+
+        metrics.requests.with_label_values(&["a"]).inc();
+        duplicate.requests.with_label_values(&["a"]).inc();
+        assert_eq!(metrics.requests.with_label_values(&["a"]).get(), 1);
+        assert_eq!(duplicate.requests.with_label_values(&["a"]).get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_process_metrics_are_served() {
+        register_process_metrics();
+
+        let response = Router::new()
+            .route("/metrics", get(metrics_handler))
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("process_resident_memory_bytes"));
+        assert!(body.contains("process_open_fds"));
+    }
+}
@@ -1,18 +1,27 @@
 // Copyright 2023-, GraphOps and Semiotic Labs.
 // SPDX-License-Identifier: Apache-2.0
 
+mod access_log;
+mod byte_rate_limit;
 mod config;
+mod error_log;
+mod graceful_shutdown;
+mod health_token;
 mod indexer_service;
 mod metrics;
+mod readiness;
 mod request_handler;
 mod static_subgraph;
 mod tap_receipt_header;
 
+pub use access_log::{UpstreamStatus, REQUEST_ID_HEADER};
 pub use config::{
     DatabaseConfig, GraphNetworkConfig, GraphNodeConfig, IndexerConfig, IndexerServiceConfig,
     ServerConfig, SubgraphConfig, TapConfig,
 };
+pub use health_token::require_health_token;
 pub use indexer_service::{
     IndexerService, IndexerServiceImpl, IndexerServiceOptions, IndexerServiceRelease,
     IndexerServiceResponse,
 };
+pub use metrics::register_or_warn;
@@ -0,0 +1,102 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::anyhow;
+use axum::extract::State;
+use thegraph::types::Address;
+
+use crate::prelude::AttestationSigner;
+
+use super::{
+    indexer_service::{IndexerServiceError, IndexerServiceState},
+    IndexerServiceImpl,
+};
+
+/// Request and response used to produce the test attestations.
+const TEST_REQUEST: &str = "readiness-check-request";
+const TEST_RESPONSE: &str = "readiness-check-response";
+
+/// Verifies that every attestation signer produces attestations that are
+/// signed by the allocation it is registered for.
+pub fn check_attestation_signers(
+    signers: &HashMap<Address, AttestationSigner>,
+) -> Result<(), anyhow::Error> {
+    for (allocation_id, signer) in signers {
+        let attestation = signer.create_attestation(TEST_REQUEST, TEST_RESPONSE);
+        signer
+            .verify(&attestation, TEST_REQUEST, TEST_RESPONSE, allocation_id)
+            .map_err(|e| {
+                anyhow!(
+                    "Attestation signer for allocation `{}` is misconfigured: {:?}",
+                    allocation_id,
+                    e
+                )
+            })?;
+    }
+    Ok(())
+}
+
+pub async fn readiness_handler<I>(
+    State(state): State<Arc<IndexerServiceState<I>>>,
+) -> Result<&'static str, IndexerServiceError<I::Error>>
+where
+    I: IndexerServiceImpl + Sync + Send + 'static,
+{
+    if state.config.server.readiness_check_attestations {
+        let signers = state
+            .attestation_signers
+            .value_immediate()
+            .ok_or(IndexerServiceError::ServiceNotReady)?;
+
+        check_attestation_signers(&signers)
+            .map_err(IndexerServiceError::AttestationSignerMisconfigured)?;
+    }
+
+    Ok("Ready")
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers_core::types::U256;
+
+    use crate::test_vectors::{
+        DISPUTE_MANAGER_ADDRESS, INDEXER_ALLOCATIONS, INDEXER_OPERATOR_MNEMONIC,
+    };
+
+    use super::*;
+
+    fn signers_for_allocations() -> HashMap<Address, AttestationSigner> {
+        INDEXER_ALLOCATIONS
+            .iter()
+            .map(|(id, allocation)| {
+                let signer = AttestationSigner::new(
+                    &INDEXER_OPERATOR_MNEMONIC,
+                    allocation,
+                    U256::from(1),
+                    *DISPUTE_MANAGER_ADDRESS,
+                )
+                .unwrap();
+                (*id, signer)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_check_attestation_signers() {
+        assert!(check_attestation_signers(&signers_for_allocations()).is_ok());
+    }
+
+    #[test]
+    fn test_check_attestation_signers_misconfigured() {
+        let signers = signers_for_allocations();
+        let mut ids = signers.keys().copied();
+        let (first, second) = (ids.next().unwrap(), ids.next().unwrap());
+
+        // Register the signer of one allocation under another allocation's ID
+        let misconfigured = HashMap::from([(first, signers[&second].clone())]);
+
+        assert!(check_attestation_signers(&misconfigured).is_err());
+    }
+}
@@ -8,6 +8,7 @@ use axum::{
     extract::{Path, State},
     http::HeaderMap,
     response::IntoResponse,
+    Extension,
 };
 use axum_extra::TypedHeader;
 use reqwest::StatusCode;
@@ -17,6 +18,7 @@ use tracing::trace;
 use crate::{indexer_service::http::IndexerServiceResponse, prelude::AttestationSigner};
 
 use super::{
+    access_log::Attestable,
     indexer_service::{IndexerServiceError, IndexerServiceState},
     tap_receipt_header::TapReceipt,
     IndexerServiceImpl,
@@ -41,8 +43,7 @@ where
         .with_label_values(&[&manifest_id.to_string()])
         .inc();
 
-    let request =
-        serde_json::from_slice(&body).map_err(|e| IndexerServiceError::InvalidRequest(e.into()))?;
+    let request = serde_json::from_slice(&body).map_err(IndexerServiceError::MalformedJson)?;
 
     let mut attestation_signer: Option<AttestationSigner> = None;
 
@@ -87,10 +88,11 @@ where
 
     let (request, response) = state
         .service_impl
-        .process_request(manifest_id, request)
+        .process_request(manifest_id, request, headers)
         .await
         .map_err(IndexerServiceError::ProcessingError)?;
 
+    let attestable = Attestable(response.is_attestable());
     let attestation = match (response.is_attestable(), attestation_signer) {
         (false, _) => None,
         (true, None) => return Err(IndexerServiceError::NoSignerForManifest(manifest_id)),
@@ -106,5 +108,5 @@ where
 
     let response = response.finalize(attestation);
 
-    Ok((StatusCode::OK, response))
+    Ok((StatusCode::OK, Extension(attestable), response))
 }
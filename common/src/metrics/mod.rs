@@ -2,13 +2,17 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use lazy_static::lazy_static;
-use prometheus::{register_int_counter_vec, IntCounterVec};
+use prometheus::{opts, IntCounterVec};
+
+use crate::indexer_service::http::register_or_warn;
 
 lazy_static! {
     /// Register indexer error metrics in Prometheus registry
-    pub static ref INDEXER_ERROR: IntCounterVec = register_int_counter_vec!(
-        "indexer_error",
-        "Indexer errors observed over time",
-        &["code"]
-    ).expect("Create indexer_error metrics");
+    pub static ref INDEXER_ERROR: IntCounterVec = register_or_warn(
+        IntCounterVec::new(
+            opts!("indexer_error", "Indexer errors observed over time"),
+            &["code"]
+        )
+        .expect("Valid metric name")
+    );
 }
@@ -13,6 +13,8 @@ use tracing::warn;
 
 use alloy_primitives::Address;
 use bip39::Mnemonic;
+use http::HeaderValue;
+use semver::VersionReq;
 use serde::Deserialize;
 use serde_with::serde_as;
 use thegraph::types::DeploymentId;
@@ -51,7 +53,7 @@ impl Config {
     pub fn parse(prefix: ConfigPrefix, filename: &PathBuf) -> Result<Self, String> {
         let config_defaults = include_str!("../default_values.toml");
 
-        let config: Self = Figment::new()
+        let mut config: Self = Figment::new()
             .merge(Toml::string(config_defaults))
             .merge(Toml::file(filename))
             .merge(Env::prefixed(prefix.get_prefix()))
@@ -63,7 +65,7 @@ impl Config {
     }
 
     // custom validation of the values
-    fn validate(&self) -> Result<(), String> {
+    fn validate(&mut self) -> Result<(), String> {
         match &self.tap.rav_request.trigger_value_divisor {
             x if *x <= 1.into() => {
                 return Err("trigger_value_divisor must be greater than 1".to_string())
@@ -106,6 +108,57 @@ impl Config {
             );
         }
 
+        let upstream = &self.service.upstream;
+        if upstream.timeout_secs == 0
+            || upstream.status_timeout_secs == Some(0)
+            || upstream.deployment_timeouts.values().any(|secs| *secs == 0)
+            || upstream
+                .status_field_timeouts
+                .values()
+                .any(|secs| *secs == 0)
+        {
+            return Err("upstream timeouts must be greater than 0".to_string());
+        }
+
+        self.service.status.cache_control_header = self
+            .service
+            .status
+            .cache_control
+            .as_deref()
+            .map(HeaderValue::from_str)
+            .transpose()
+            .map_err(|e| format!("status.cache_control is not a valid header value: {e}"))?;
+
+        if self.service.admin_token.as_deref() == Some("") {
+            return Err("admin_token must not be empty".to_string());
+        }
+
+        if self.service.query.max_concurrent_queries == Some(0) {
+            return Err("max_concurrent_queries must be greater than 0".to_string());
+        }
+        if self
+            .service
+            .query
+            .deployment_registry_max_concurrent_lookups
+            == 0
+        {
+            return Err(
+                "deployment_registry_max_concurrent_lookups must be greater than 0".to_string(),
+            );
+        }
+
+        if let Some((operation, _)) = self
+            .service
+            .query
+            .operation_cost_multipliers
+            .iter()
+            .find(|(_, multiplier)| !multiplier.is_finite() || **multiplier < 0.0)
+        {
+            return Err(format!(
+                "operation_cost_multipliers.{operation} must be a finite, non-negative number"
+            ));
+        }
+
         if self.tap.rav_request.timestamp_buffer_secs < Duration::from_secs(10) {
             warn!(
                 "Your `tap.rav_request.timestamp_buffer_secs` value it too low. \
@@ -142,6 +195,8 @@ pub struct GraphNodeConfig {
 #[cfg_attr(test, derive(PartialEq))]
 pub struct MetricsConfig {
     pub port: u16,
+    /// also export CPU, memory and open file descriptor metrics of the process
+    pub process_metrics: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -199,7 +254,7 @@ pub struct BlockchainConfig {
     pub receipts_verifier_address: Address,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct ServiceConfig {
     pub serve_network_subgraph: bool,
@@ -208,17 +263,247 @@ pub struct ServiceConfig {
     pub host_and_port: SocketAddr,
     pub url_prefix: String,
     pub tap: ServiceTapConfig,
+    pub upstream: ServiceUpstreamConfig,
+    pub status: ServiceStatusConfig,
+    pub query: ServiceQueryConfig,
     pub free_query_auth_token: Option<String>,
+    /// whether `/readyz` should verify that attestations can be signed
+    pub readiness_check_attestations: bool,
+    /// log queries on a single line
+    pub log_query_collapse_whitespace: bool,
+    /// treat `/path/` and `/path` as different routes
+    pub strict_trailing_slash: bool,
+    /// report the time spent in each request phase in a `Server-Timing` header
+    pub server_timing_header: bool,
+    /// rules applied to query and status responses, in order
+    pub response_transforms: Vec<ResponseTransform>,
+    /// replace graph-node error messages with a generic one
+    pub sanitize_upstream_errors: bool,
+    /// return the normalized query hash in `extensions.queryHash` of query and
+    /// status responses
+    pub echo_query_hash: bool,
+    /// serve cost models on `/cost`
+    pub enable_cost_route: bool,
+    /// describe the service and its routes in JSON on `/`
+    pub enable_root_info: bool,
+    /// report the commit the service was built from in an `X-Indexer-Build`
+    /// response header
+    pub build_header: bool,
+    /// requests with larger bodies fail with 413 Payload Too Large
+    pub max_request_bytes: usize,
+    /// how long in-flight requests may keep running after SIGTERM before they
+    /// are abandoned
+    pub shutdown_grace_secs: u64,
+    /// StatsD/DogStatsD server to mirror metrics to
+    pub statsd_address: Option<String>,
+    /// log identical request errors at most once per interval
+    pub error_log_min_interval_secs: Option<u64>,
+    /// file to write span timings to, in the Chrome trace format
+    pub trace_export_file: Option<PathBuf>,
+    /// requests taking longer than this fail with 504 Gateway Timeout
+    pub global_request_timeout_secs: Option<u64>,
+    /// bearer token required on the health routes
+    pub health_token: Option<String>,
+    /// bearer token required on `/admin/reload`, which is only served if set
+    pub admin_token: Option<String>,
+    /// JSON file with per-deployment toggles that can turn attestation off,
+    /// reloaded on change
+    pub attestation_overrides_file: Option<PathBuf>,
+    /// directory of per-deployment cost model files, reloaded on change
+    pub cost_models_dir: Option<PathBuf>,
+    /// bytes of query payload each client may send per second
+    pub byte_rate_limit_per_client: Option<u64>,
 }
 
 #[serde_as]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct ServiceTapConfig {
     /// what's the maximum value we accept in a receipt
     pub max_receipt_value_grt: NonZeroGRT,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct ServiceUpstreamConfig {
+    /// proxy for plain HTTP requests to graph-node, overrides `HTTP_PROXY`
+    pub http_proxy: Option<Url>,
+    /// proxy for HTTPS requests to graph-node, overrides `HTTPS_PROXY`
+    pub https_proxy: Option<Url>,
+    /// comma-separated hosts that bypass the configured proxies
+    pub no_proxy: Option<String>,
+    /// how often a failed status query is retried
+    pub status_max_retries: u32,
+    /// how often a failed forwarded query is retried
+    pub query_max_retries: u32,
+    /// delay before the first retry of a forwarded query, doubled for each
+    /// further retry
+    pub query_retry_base_delay_ms: u64,
+    /// number of errors within the window that triggers a cooldown
+    pub error_cooldown_threshold: Option<u32>,
+    /// window in which errors are counted towards the cooldown threshold
+    pub error_cooldown_window_ms: u64,
+    /// how long to hold back forwards after the last error once in cooldown
+    pub error_cooldown_ms: u64,
+    /// consecutive failed graph-node requests that open the circuit breaker
+    pub circuit_breaker_threshold: Option<u32>,
+    /// how long the open circuit breaker fails requests before trying again
+    pub circuit_breaker_cooldown_secs: u64,
+    /// how long consecutive failures must have been going on before they open
+    /// the circuit breaker
+    pub circuit_min_open_duration_ms: u64,
+    /// timeout of requests to graph-node, in seconds
+    pub timeout_secs: u64,
+    /// timeout of status requests to graph-node if different, in seconds
+    pub status_timeout_secs: Option<u64>,
+    /// `Accept` header of queries forwarded to graph-node
+    pub accept_header: String,
+    /// timeouts for queries to specific deployments, in seconds
+    pub deployment_timeouts: HashMap<DeploymentId, u64>,
+    /// timeouts for status queries selecting specific root fields, in seconds
+    pub status_field_timeouts: HashMap<String, u64>,
+    /// status query that must return data without errors on startup
+    pub startup_validation_query: Option<String>,
+    /// values the startup validation query must return, by JSON pointer into
+    /// its `data`
+    pub startup_validation_expected: HashMap<String, serde_json::Value>,
+    /// key to sign forwarded query bodies with, in an `X-Signature` header
+    pub hmac_signing_key: Option<String>,
+    /// versions of graph-node the service may run against, checked on startup
+    pub required_version: Option<VersionReq>,
+    /// abort startup instead of warning if the validation query fails
+    pub startup_validation_required: bool,
+    /// abort startup instead of warning if graph-node's version doesn't
+    /// satisfy `required_version`
+    pub required_version_strict: bool,
+    /// how long a failed graph-node request makes `/ready` report the service
+    /// as not ready
+    pub readiness_window_secs: u64,
+    /// ask graph-node for gzip, brotli or deflate compressed responses
+    pub compress: bool,
+    /// client headers passed on with forwarded queries
+    pub forward_headers: Vec<String>,
+    /// headers never forwarded, even if listed in `forward_headers`
+    pub strip_forwarded_headers: Vec<String>,
+    /// total size of the forwarded headers, further headers are dropped
+    pub max_forwarded_header_bytes: usize,
+    /// idle connections kept open to graph-node, unlimited if not set
+    pub pool_max_idle_per_host: Option<usize>,
+    /// how long idle connections to graph-node are kept open, 0 keeps them
+    /// open indefinitely
+    pub pool_idle_timeout_secs: u64,
+    /// talk HTTP/2 to graph-node without negotiating it first
+    pub http2_prior_knowledge: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct ServiceStatusConfig {
+    /// query executed when `/status` receives an empty body
+    pub default_query: Option<String>,
+    /// status queries slower than this count as SLO violations
+    pub slo_ms: Option<u64>,
+    /// reject `blockData` queries without a valid block hash before forwarding
+    pub validate_block_data: bool,
+    /// chains status queries must not target
+    pub disabled_chains: Vec<String>,
+    /// root fields allowed in status queries besides the built-in ones
+    pub extra_root_fields: Vec<String>,
+    /// fields of `indexingStatuses` results returned unless the client asks
+    /// for others
+    pub indexing_statuses_default_fields: Option<Vec<String>>,
+    /// `Cache-Control` header of `/status` responses
+    pub cache_control: Option<String>,
+    /// `cache_control` as a header value, set when the configuration is
+    /// validated
+    #[serde(skip)]
+    pub cache_control_header: Option<HeaderValue>,
+    /// variables status queries may use
+    pub allowed_variables: Option<Vec<String>>,
+    /// names reported instead of the given subgraph IDs in status responses
+    pub subgraph_id_aliases: HashMap<String, String>,
+    /// fields, by dotted path, that status responses warn about, with a hint
+    /// on what to use instead
+    pub deprecated_fields: HashMap<String, String>,
+    /// how long successful status query results are served from memory,
+    /// 0 disables caching
+    pub cache_ttl_secs: u64,
+    /// refresh cached status results in the background once they are this
+    /// close to expiring, 0 disables refreshing
+    pub cache_refresh_ahead_ms: u64,
+    /// how many status query results to cache at most
+    pub cache_max_entries: usize,
+    /// how many cached status results to refresh in the background at once
+    pub max_concurrent_cache_refreshes: usize,
+    /// poll graph-node for `earliestBlock` and `latestBlock` this often and
+    /// serve queries for only these from the polled values
+    pub block_poll_interval_secs: Option<u64>,
+    /// log status responses only when they differ from the previous response
+    /// to the same query
+    pub log_status_diffs_only: bool,
+    /// report the graph-node status URL in `extensions.backend` of
+    /// `/status?debug=true` responses
+    pub debug_reports_backend: bool,
+    /// root fields allowed by each status API version served on
+    /// `/status/:version`
+    pub versions: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct ServiceQueryConfig {
+    /// reject queries with inline argument literals other than enums and booleans
+    pub require_variables: bool,
+    /// echo the deployment ID in an `X-Deployment-Id` response header
+    pub echo_deployment_header: bool,
+    /// validate the shape of query requests against a JSON schema
+    pub validate_request_schema: bool,
+    /// reject queries with `extensions` other than the known ones
+    pub reject_unknown_extensions: bool,
+    /// report the graph-node round-trip time in an `X-Upstream-Latency-Ms` header
+    pub upstream_latency_header: bool,
+    /// deployments that are no longer served, queries for them get a 410
+    pub deprecated_deployments: Vec<DeploymentId>,
+    /// deployments queries are served for, all if empty
+    pub allowed_deployments: Vec<DeploymentId>,
+    /// deployments queries are never served for
+    pub denied_deployments: Vec<DeploymentId>,
+    /// GraphQL endpoint of a subgraph registry that queried deployments must
+    /// be registered in
+    pub deployment_registry_url: Option<Url>,
+    /// how long to remember whether a deployment is registered
+    pub deployment_registry_cache_secs: u64,
+    /// how many registry answers to remember at most
+    pub deployment_registry_cache_max_entries: usize,
+    /// how many deployments to look up in the registry at once
+    pub deployment_registry_max_concurrent_lookups: usize,
+    /// never attest responses that contain GraphQL errors
+    pub no_attest_on_errors: bool,
+    /// how many Automatic Persisted Queries to remember, 0 disables them
+    pub persisted_queries_max_entries: usize,
+    /// reject requests without a query instead of forwarding them
+    pub require_query: bool,
+    /// forward at most this many queries at once, queueing the others by
+    /// their `X-Priority` header
+    pub max_concurrent_queries: Option<usize>,
+    /// reject queries selecting the same field with identical arguments more
+    /// often than this within one selection set
+    pub max_repeated_selections: Option<usize>,
+    /// factors applied to the estimated cost of queries by operation name
+    pub operation_cost_multipliers: HashMap<String, f64>,
+    /// metadata returned in `extensions.deployment` of query responses
+    pub deployment_metadata: HashMap<DeploymentId, HashMap<String, String>>,
+}
+
+/// Change of a response at the locations matched by a JSONPath.
+#[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(test, derive(PartialEq))]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum ResponseTransform {
+    Remove { path: String },
+    Rename { path: String, to: String },
+}
+
 #[derive(Debug, Deserialize)]
 #[cfg_attr(test, derive(PartialEq))]
 pub struct TapConfig {
@@ -288,4 +573,81 @@ mod tests {
 
         assert_eq!(max_config, max_config_file);
     }
+
+    #[test]
+    fn test_zero_upstream_timeouts_are_rejected() {
+        let config = || {
+            Config::parse(
+                ConfigPrefix::Service,
+                &PathBuf::from("minimal-config-example.toml"),
+            )
+            .unwrap()
+        };
+
+        let mut zero_timeout = config();
+        zero_timeout.service.upstream.timeout_secs = 0;
+        assert!(zero_timeout.validate().is_err());
+
+        let mut zero_status_timeout = config();
+        zero_status_timeout.service.upstream.status_timeout_secs = Some(0);
+        assert!(zero_status_timeout.validate().is_err());
+    }
+
+    #[test]
+    fn test_zero_max_concurrent_queries_is_rejected() {
+        let mut config = Config::parse(
+            ConfigPrefix::Service,
+            &PathBuf::from("minimal-config-example.toml"),
+        )
+        .unwrap();
+        config.service.query.max_concurrent_queries = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_empty_admin_token_is_rejected() {
+        let mut config = Config::parse(
+            ConfigPrefix::Service,
+            &PathBuf::from("minimal-config-example.toml"),
+        )
+        .unwrap();
+        config.service.admin_token = Some(String::new());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_invalid_cache_control_is_rejected() {
+        let mut config = Config::parse(
+            ConfigPrefix::Service,
+            &PathBuf::from("minimal-config-example.toml"),
+        )
+        .unwrap();
+        config.service.status.cache_control = Some("max-age=5\n".to_string());
+        assert!(config.validate().is_err());
+
+        config.service.status.cache_control = Some("max-age=5".to_string());
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.service.status.cache_control_header,
+            Some(HeaderValue::from_static("max-age=5"))
+        );
+    }
+
+    #[test]
+    fn test_invalid_operation_cost_multipliers_are_rejected() {
+        let mut config = Config::parse(
+            ConfigPrefix::Service,
+            &PathBuf::from("minimal-config-example.toml"),
+        )
+        .unwrap();
+        for multiplier in [f64::NAN, f64::INFINITY, -1.0] {
+            config.service.query.operation_cost_multipliers =
+                HashMap::from([("expensive".to_string(), multiplier)]);
+            assert!(config.validate().is_err());
+        }
+
+        config.service.query.operation_cost_multipliers =
+            HashMap::from([("expensive".to_string(), 2.5), ("free".to_string(), 0.0)]);
+        assert!(config.validate().is_ok());
+    }
 }
@@ -0,0 +1,120 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use thegraph::types::DeploymentId;
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Per-deployment attestation toggles. Responses are only attested if
+/// graph-node marks them as attestable; `false` turns attestation off for a
+/// deployment, while `true` or no entry leaves it up to graph-node.
+pub type AttestationOverrides = Arc<RwLock<HashMap<DeploymentId, bool>>>;
+
+/// Read the overrides file, a JSON object mapping deployment IDs to whether
+/// their responses may be attested.
+pub fn load(path: &Path) -> anyhow::Result<HashMap<DeploymentId, bool>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read attestation overrides file {path:?}"))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse attestation overrides file {path:?}"))
+}
+
+/// Load the overrides file and keep reloading it whenever it changes.
+pub fn watch(path: PathBuf) -> anyhow::Result<AttestationOverrides> {
+    watch_with_interval(path, POLL_INTERVAL)
+}
+
+pub fn watch_with_interval(
+    path: PathBuf,
+    interval: Duration,
+) -> anyhow::Result<AttestationOverrides> {
+    let overrides = Arc::new(RwLock::new(load(&path)?));
+
+    let watched = overrides.clone();
+    let mut last_modified = modified(&path);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let modified = modified(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            // Keep the previous overrides if the file is broken, e.g. while
+            // it is being written
+            match load(&path) {
+                Ok(overrides) => {
+                    info!(?path, "Reloaded attestation overrides");
+                    *watched.write().unwrap() = overrides;
+                }
+                Err(e) => warn!(error = %e, "Failed to reload attestation overrides"),
+            }
+        }
+    });
+
+    Ok(overrides)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, str::FromStr};
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    const DEPLOYMENT: &str = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+
+    fn write(file: &mut NamedTempFile, contents: &str) {
+        let file = file.as_file_mut();
+        file.set_len(0).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file.sync_all().unwrap();
+        // Make sure the change is visible even on coarse mtime resolutions
+        file.set_modified(SystemTime::now() + Duration::from_secs(1))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overrides_are_reloaded_on_change() {
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let mut file = NamedTempFile::new().unwrap();
+        write(&mut file, &format!(r#"{{ "{DEPLOYMENT}": false }}"#));
+
+        let overrides =
+            watch_with_interval(file.path().to_path_buf(), Duration::from_millis(10)).unwrap();
+        assert_eq!(overrides.read().unwrap().get(&deployment), Some(&false));
+
+        write(&mut file, &format!(r#"{{ "{DEPLOYMENT}": true }}"#));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while overrides.read().unwrap().get(&deployment) != Some(&true) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("overrides should be reloaded");
+    }
+
+    #[test]
+    fn test_invalid_overrides_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        write(&mut file, r#"{ "not-a-deployment": false }"#);
+        assert!(load(file.path()).is_err());
+    }
+}
@@ -26,8 +26,19 @@ impl From<MainConfig> for Config {
                     Ipv4Addr::new(0, 0, 0, 0),
                     value.metrics.port,
                 )),
+                process_metrics: value.metrics.process_metrics,
                 url_prefix: value.service.url_prefix,
                 free_query_auth_token: value.service.free_query_auth_token,
+                readiness_check_attestations: value.service.readiness_check_attestations,
+                strict_trailing_slash: value.service.strict_trailing_slash,
+                error_log_min_interval_secs: value.service.error_log_min_interval_secs,
+                global_request_timeout_secs: value.service.global_request_timeout_secs,
+                health_token: value.service.health_token,
+                enable_root_info: value.service.enable_root_info,
+                build_header: value.service.build_header,
+                byte_rate_limit_per_client: value.service.byte_rate_limit_per_client,
+                max_request_bytes: value.service.max_request_bytes,
+                shutdown_grace_secs: value.service.shutdown_grace_secs,
             },
             database: DatabaseConfig {
                 postgres_url: value.database.postgres_url.into(),
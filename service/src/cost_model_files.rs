@@ -0,0 +1,176 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use thegraph::types::DeploymentId;
+use tracing::{info, warn};
+
+use crate::database::CostModel;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Cost models read from the cost models directory, by deployment. These take
+/// precedence over the cost models in the database.
+pub type CostModelFiles = Arc<RwLock<HashMap<DeploymentId, CostModel>>>;
+
+/// Read the cost models directory. Each file holds the Agora model of the
+/// deployment it is named after, e.g. `Qm....agora`; other files are skipped.
+pub fn load(dir: &Path) -> anyhow::Result<HashMap<DeploymentId, CostModel>> {
+    let mut models = HashMap::new();
+    for (path, _) in files(dir)? {
+        let Some(deployment) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| DeploymentId::from_str(stem).ok())
+        else {
+            warn!(
+                ?path,
+                "Skipping cost model file not named after a deployment"
+            );
+            continue;
+        };
+        let model = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read cost model file {path:?}"))?;
+        models.insert(
+            deployment,
+            CostModel {
+                deployment,
+                model: Some(model),
+                variables: None,
+            },
+        );
+    }
+    Ok(models)
+}
+
+/// Load the cost models directory and keep reloading it whenever files are
+/// added, changed or removed.
+pub fn watch(dir: PathBuf) -> anyhow::Result<CostModelFiles> {
+    watch_with_interval(dir, POLL_INTERVAL)
+}
+
+pub fn watch_with_interval(dir: PathBuf, interval: Duration) -> anyhow::Result<CostModelFiles> {
+    let models = Arc::new(RwLock::new(load(&dir)?));
+
+    let watched = models.clone();
+    let mut last_files = files(&dir).ok();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+
+            let files = files(&dir).ok();
+            if files == last_files {
+                continue;
+            }
+            last_files = files;
+
+            // Keep the previous models if the directory can't be read
+            match load(&dir) {
+                Ok(models) => {
+                    info!(?dir, count = models.len(), "Reloaded cost model files");
+                    *watched.write().unwrap() = models;
+                }
+                Err(e) => warn!(error = %e, "Failed to reload cost model files"),
+            }
+        }
+    });
+
+    Ok(models)
+}
+
+/// Files in the directory with their modification times, sorted by path.
+fn files(dir: &Path) -> anyhow::Result<Vec<(PathBuf, Option<SystemTime>)>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read cost models directory {dir:?}"))?
+    {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push((entry.path(), metadata.modified().ok()));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const DEPLOYMENT: &str = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+    const OTHER_DEPLOYMENT: &str = "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB";
+
+    fn model(models: &CostModelFiles, deployment: &str) -> Option<String> {
+        models
+            .read()
+            .unwrap()
+            .get(&DeploymentId::from_str(deployment).unwrap())
+            .and_then(|model| model.model.clone())
+    }
+
+    #[test]
+    fn test_models_are_loaded_from_directory() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join(format!("{DEPLOYMENT}.agora")),
+            "default => 1;",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("README"), "not a model").unwrap();
+
+        let models = load(dir.path()).unwrap();
+        assert_eq!(models.len(), 1);
+        assert_eq!(
+            models[&DeploymentId::from_str(DEPLOYMENT).unwrap()].model,
+            Some("default => 1;".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_added_files_are_picked_up() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join(format!("{DEPLOYMENT}.agora")),
+            "default => 1;",
+        )
+        .unwrap();
+
+        let models =
+            watch_with_interval(dir.path().to_path_buf(), Duration::from_millis(10)).unwrap();
+        assert_eq!(model(&models, OTHER_DEPLOYMENT), None);
+
+        std::fs::write(
+            dir.path().join(format!("{OTHER_DEPLOYMENT}.agora")),
+            "default => 2;",
+        )
+        .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while model(&models, OTHER_DEPLOYMENT).is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("added cost model should be loaded");
+        assert_eq!(
+            model(&models, DEPLOYMENT),
+            Some("default => 1;".to_string())
+        );
+    }
+
+    #[test]
+    fn test_missing_directory() {
+        assert!(load(Path::new("/nonexistent/cost-models")).is_err());
+    }
+}
@@ -2,8 +2,15 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::Error;
-use axum::response::{IntoResponse, Response};
+use axum::{
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use bigdecimal::BigDecimal;
+use indexer_common::indexer_service::http::UpstreamStatus;
 use reqwest::StatusCode;
+use serde_json::json;
 use thegraph::types::DeploymentId;
 use thiserror::Error;
 
@@ -13,12 +20,83 @@ pub enum SubgraphServiceError {
     InvalidStatusQuery(Error),
     #[error("Unsupported status query fields: {0:?}")]
     UnsupportedStatusQueryFields(Vec<String>),
+    #[error("Unsupported cost query fields: {0:?}")]
+    UnsupportedCostQueryFields(Vec<String>),
     #[error("Internal server error: {0}")]
     StatusQueryError(Error),
+    #[error("Status query variables are not allowed: {0:?}")]
+    DisallowedStatusVariables(Vec<String>),
+    #[error("Unknown status API version `{0}`")]
+    UnknownStatusVersion(String),
+    #[error("Graph node returned an empty status response")]
+    EmptyStatusResponse,
     #[error("Invalid deployment: {0}")]
     InvalidDeployment(DeploymentId),
+    #[error("Deployment {0} is not served by this indexer")]
+    DeploymentNotAllowed(DeploymentId),
+    #[error("Deployment {0} is no longer served by this indexer")]
+    DeploymentGone(DeploymentId),
+    #[error("Deployment {0} is not registered")]
+    DeploymentNotRegistered(DeploymentId),
+    #[error("Failed to check the deployment registry: {0}")]
+    DeploymentRegistryError(Error),
     #[error("Failed to process query: {0}")]
     QueryForwardingError(reqwest::Error),
+    #[error("Graph node closed the connection before sending the full response: {0}")]
+    UpstreamConnectionReset(reqwest::Error),
+    #[error("Graph node is unavailable, try again later")]
+    UpstreamUnavailable,
+    #[error("Graph node responded with `{0}` instead of JSON, check the graph-node URL")]
+    UpstreamNonJson(String),
+    #[error("Graph node responded with {status}: {message}")]
+    UpstreamErrorStatus {
+        status: StatusCode,
+        message: String,
+        retry_after: Option<HeaderValue>,
+    },
+    #[error("Invalid maximum query cost: {0}")]
+    InvalidMaxQueryCost(Error),
+    #[error("Invalid cost model: {0}")]
+    InvalidCostModel(Error),
+    #[error("Failed to estimate query cost: {0}")]
+    QueryCostEstimationError(Error),
+    #[error("Invalid request: {0:?}")]
+    InvalidRequestSchema(Vec<String>),
+    #[error("Unknown request extensions: {0:?}")]
+    UnknownExtensions(Vec<String>),
+    #[error("PersistedQueryNotFound")]
+    PersistedQueryNotFound,
+    #[error("Provided sha256Hash does not match the query")]
+    PersistedQueryHashMismatch,
+    #[error("Request has no query")]
+    MissingQuery,
+    #[error("Invalid query: {0}")]
+    InvalidQuery(Error),
+    #[error("Arguments must be passed as variables: {0:?}")]
+    InlineArgumentLiterals(Vec<String>),
+    #[error(
+        "Field `{field}` is selected {count} times, at most {max} identical selections are allowed"
+    )]
+    RepetitiveQuery {
+        field: String,
+        count: usize,
+        max: usize,
+    },
+    #[error("Invalid `blockData` query: {0}")]
+    InvalidBlockDataQuery(String),
+    #[error("Unknown feature flags: {0:?}")]
+    UnknownFeatureFlags(Vec<String>),
+    #[error("Chain `{0}` is disabled on this indexer")]
+    DisabledChain(String),
+    #[error("Missing or invalid admin token")]
+    AdminUnauthorized,
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Query cost of {cost} GRT wei exceeds the maximum of {max_cost} GRT wei")]
+    QueryCostExceeded {
+        cost: BigDecimal,
+        max_cost: BigDecimal,
+    },
 }
 
 impl From<&SubgraphServiceError> for StatusCode {
@@ -27,9 +105,80 @@ impl From<&SubgraphServiceError> for StatusCode {
         match err {
             InvalidStatusQuery(_) => StatusCode::BAD_REQUEST,
             UnsupportedStatusQueryFields(_) => StatusCode::BAD_REQUEST,
+            UnsupportedCostQueryFields(_) => StatusCode::BAD_REQUEST,
             StatusQueryError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            EmptyStatusResponse => StatusCode::BAD_GATEWAY,
+            UnknownStatusVersion(_) => StatusCode::NOT_FOUND,
+            DisallowedStatusVariables(_) => StatusCode::BAD_REQUEST,
             InvalidDeployment(_) => StatusCode::BAD_REQUEST,
+            DeploymentNotAllowed(_) => StatusCode::FORBIDDEN,
+            DeploymentGone(_) => StatusCode::GONE,
+            DeploymentNotRegistered(_) => StatusCode::NOT_FOUND,
+            DeploymentRegistryError(_) => StatusCode::BAD_GATEWAY,
             QueryForwardingError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            UpstreamConnectionReset(_) => StatusCode::BAD_GATEWAY,
+            UpstreamUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+            UpstreamNonJson(_) => StatusCode::BAD_GATEWAY,
+            UpstreamErrorStatus { status, .. } => *status,
+            InvalidMaxQueryCost(_) => StatusCode::BAD_REQUEST,
+            InvalidCostModel(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            QueryCostEstimationError(_) => StatusCode::BAD_REQUEST,
+            QueryCostExceeded { .. } => StatusCode::PAYMENT_REQUIRED,
+            InvalidRequestSchema(_) => StatusCode::BAD_REQUEST,
+            UnknownExtensions(_) => StatusCode::BAD_REQUEST,
+            PersistedQueryNotFound | PersistedQueryHashMismatch => StatusCode::BAD_REQUEST,
+            MissingQuery => StatusCode::BAD_REQUEST,
+            InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            InlineArgumentLiterals(_) => StatusCode::BAD_REQUEST,
+            RepetitiveQuery { .. } => StatusCode::BAD_REQUEST,
+            InvalidBlockDataQuery(_) => StatusCode::BAD_REQUEST,
+            UnknownFeatureFlags(_) => StatusCode::BAD_REQUEST,
+            DisabledChain(_) => StatusCode::BAD_REQUEST,
+            AdminUnauthorized => StatusCode::UNAUTHORIZED,
+            InvalidConfig(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl SubgraphServiceError {
+    /// Stable, machine-readable identifier of the error.
+    pub fn code(&self) -> &'static str {
+        use SubgraphServiceError::*;
+        match self {
+            InvalidStatusQuery(_) => "INVALID_STATUS_QUERY",
+            UnsupportedStatusQueryFields(_) => "UNSUPPORTED_STATUS_FIELDS",
+            UnsupportedCostQueryFields(_) => "UNSUPPORTED_COST_FIELDS",
+            StatusQueryError(_) => "STATUS_QUERY_FAILED",
+            EmptyStatusResponse => "EMPTY_STATUS_RESPONSE",
+            UnknownStatusVersion(_) => "UNKNOWN_STATUS_VERSION",
+            DisallowedStatusVariables(_) => "DISALLOWED_STATUS_VARIABLES",
+            InvalidDeployment(_) => "INVALID_DEPLOYMENT",
+            DeploymentNotAllowed(_) => "DEPLOYMENT_NOT_ALLOWED",
+            DeploymentGone(_) => "DEPLOYMENT_GONE",
+            DeploymentNotRegistered(_) => "DEPLOYMENT_NOT_REGISTERED",
+            DeploymentRegistryError(_) => "DEPLOYMENT_REGISTRY_FAILED",
+            QueryForwardingError(_) => "QUERY_FORWARDING_FAILED",
+            UpstreamConnectionReset(_) => "UPSTREAM_CONNECTION_RESET",
+            UpstreamUnavailable => "UPSTREAM_UNAVAILABLE",
+            UpstreamNonJson(_) => "UPSTREAM_NON_JSON",
+            UpstreamErrorStatus { .. } => "UPSTREAM_ERROR_STATUS",
+            InvalidMaxQueryCost(_) => "INVALID_MAX_QUERY_COST",
+            InvalidCostModel(_) => "INVALID_COST_MODEL",
+            QueryCostEstimationError(_) => "QUERY_COST_ESTIMATION_FAILED",
+            QueryCostExceeded { .. } => "QUERY_COST_EXCEEDED",
+            InvalidRequestSchema(_) => "INVALID_REQUEST",
+            UnknownExtensions(_) => "UNKNOWN_EXTENSIONS",
+            PersistedQueryNotFound => "PERSISTED_QUERY_NOT_FOUND",
+            PersistedQueryHashMismatch => "PERSISTED_QUERY_HASH_MISMATCH",
+            MissingQuery => "MISSING_QUERY",
+            InvalidQuery(_) => "INVALID_QUERY",
+            InlineArgumentLiterals(_) => "INLINE_ARGUMENT_LITERALS",
+            RepetitiveQuery { .. } => "REPETITIVE_QUERY",
+            InvalidBlockDataQuery(_) => "INVALID_BLOCK_DATA_QUERY",
+            UnknownFeatureFlags(_) => "UNKNOWN_FEATURE_FLAGS",
+            DisabledChain(_) => "DISABLED_CHAIN",
+            AdminUnauthorized => "ADMIN_UNAUTHORIZED",
+            InvalidConfig(_) => "INVALID_CONFIG",
         }
     }
 }
@@ -37,6 +186,113 @@ impl From<&SubgraphServiceError> for StatusCode {
 // Tell axum how to convert `SubgraphServiceError` into a response.
 impl IntoResponse for SubgraphServiceError {
     fn into_response(self) -> Response {
-        (StatusCode::from(&self), self.to_string()).into_response()
+        // Details go in `extensions`, as recommended by the GraphQL spec. The
+        // code is also kept at the top level for clients that read it there.
+        let mut extensions = json!({ "code": self.code() });
+        if let SubgraphServiceError::UnsupportedStatusQueryFields(fields)
+        | SubgraphServiceError::UnsupportedCostQueryFields(fields) = &self
+        {
+            extensions["fields"] = json!(fields);
+        }
+        let error = json!({
+            "message": self.to_string(),
+            "code": self.code(),
+            "extensions": extensions,
+        });
+        let mut response =
+            (StatusCode::from(&self), Json(json!({ "errors": [error] }))).into_response();
+        if let SubgraphServiceError::UpstreamErrorStatus {
+            status,
+            retry_after,
+            ..
+        } = self
+        {
+            response
+                .extensions_mut()
+                .insert(UpstreamStatus(status.as_u16()));
+            // Tell rate limited clients when to try again
+            if let Some(retry_after) = retry_after {
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, retry_after);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    async fn error_body(error: SubgraphServiceError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_graphql_json() {
+        let deployment: DeploymentId = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+            .parse()
+            .unwrap();
+        let (status, body) = error_body(SubgraphServiceError::InvalidDeployment(deployment)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({
+                "errors": [{
+                    "message": format!("Invalid deployment: {deployment}"),
+                    "code": "INVALID_DEPLOYMENT",
+                    "extensions": { "code": "INVALID_DEPLOYMENT" },
+                }]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_status_fields_are_listed() {
+        let (_, body) = error_body(SubgraphServiceError::UnsupportedStatusQueryFields(vec![
+            "foo".to_string(),
+            "bar".to_string(),
+        ]))
+        .await;
+        assert_eq!(
+            body["errors"][0]["extensions"]["code"],
+            "UNSUPPORTED_STATUS_FIELDS"
+        );
+        assert_eq!(
+            body["errors"][0]["extensions"]["fields"],
+            json!(["foo", "bar"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_rate_limit_is_passed_on() {
+        let response = SubgraphServiceError::UpstreamErrorStatus {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            message: "slow down".to_string(),
+            retry_after: Some(HeaderValue::from_static("5")),
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "5");
+    }
+
+    #[tokio::test]
+    async fn test_persisted_query_not_found() {
+        let (status, body) = error_body(SubgraphServiceError::PersistedQueryNotFound).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["errors"][0]["message"], "PersistedQueryNotFound");
+        assert_eq!(body["errors"][0]["code"], "PERSISTED_QUERY_NOT_FOUND");
+        assert_eq!(
+            body["errors"][0]["extensions"]["code"],
+            "PERSISTED_QUERY_NOT_FOUND"
+        );
     }
 }
@@ -0,0 +1,104 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{collections::HashSet, str::FromStr};
+
+use axum::http::HeaderMap;
+
+use crate::error::SubgraphServiceError;
+
+/// Header clients use to opt into experimental behavior for a single request,
+/// as a comma-separated list of feature flags.
+pub const FEATURES_HEADER: &str = "x-indexer-features";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Validate the request body against the GraphQL request schema
+    ValidateRequestSchema,
+    /// Echo the deployment ID in the response headers
+    EchoDeploymentId,
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "validate-request-schema" => Ok(Feature::ValidateRequestSchema),
+            "echo-deployment-id" => Ok(Feature::EchoDeploymentId),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+/// Feature flags enabled for a request.
+#[derive(Debug, Default)]
+pub struct Features(HashSet<Feature>);
+
+impl Features {
+    /// Parse the features header, rejecting unknown flags.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, SubgraphServiceError> {
+        let mut features = HashSet::new();
+        let mut unknown = Vec::new();
+        for value in headers.get_all(FEATURES_HEADER) {
+            let value = value.to_str().map_err(|_| {
+                SubgraphServiceError::UnknownFeatureFlags(vec![String::from_utf8_lossy(
+                    value.as_bytes(),
+                )
+                .to_string()])
+            })?;
+            for flag in value.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                match flag.parse() {
+                    Ok(feature) => {
+                        features.insert(feature);
+                    }
+                    Err(flag) => unknown.push(flag),
+                }
+            }
+        }
+
+        if !unknown.is_empty() {
+            return Err(SubgraphServiceError::UnknownFeatureFlags(unknown));
+        }
+        Ok(Self(features))
+    }
+
+    pub fn enabled(&self, feature: Feature) -> bool {
+        self.0.contains(&feature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_features_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FEATURES_HEADER,
+            HeaderValue::from_static("echo-deployment-id, validate-request-schema"),
+        );
+        let features = Features::from_headers(&headers).unwrap();
+        assert!(features.enabled(Feature::EchoDeploymentId));
+        assert!(features.enabled(Feature::ValidateRequestSchema));
+
+        let features = Features::from_headers(&HeaderMap::new()).unwrap();
+        assert!(!features.enabled(Feature::EchoDeploymentId));
+    }
+
+    #[test]
+    fn test_unknown_features_are_rejected() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FEATURES_HEADER,
+            HeaderValue::from_static("echo-deployment-id,time-travel"),
+        );
+        assert!(matches!(
+            Features::from_headers(&headers),
+            Err(SubgraphServiceError::UnknownFeatureFlags(flags)) if flags == vec!["time-travel"]
+        ));
+    }
+}
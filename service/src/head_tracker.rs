@@ -0,0 +1,244 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use graphql::graphql_parser::query as q;
+use serde_json::{json, Map, Value};
+use tracing::{debug, warn};
+
+/// Root fields served from the tracked block heads.
+const HEAD_FIELDS: &[&str] = &["earliestBlock", "latestBlock"];
+
+/// Query polled for the block heads.
+const HEAD_QUERY: &str = "{ earliestBlock { number hash } latestBlock { number hash } }";
+
+/// Number of poll intervals after which the block heads are too old to serve.
+const MAX_AGE_POLL_INTERVALS: u32 = 3;
+
+/// The `earliestBlock` and `latestBlock` status results, polled from
+/// graph-node in the background. These change every block, but dashboards
+/// tend to ask for them more often than that.
+#[derive(Clone)]
+pub struct HeadTracker {
+    /// The block heads and when they were polled
+    heads: Arc<RwLock<Option<(Map<String, Value>, Instant)>>>,
+    max_age: Duration,
+}
+
+impl HeadTracker {
+    fn new(max_age: Duration) -> Self {
+        Self {
+            heads: Default::default(),
+            max_age,
+        }
+    }
+
+    /// Start polling graph-node's status API every `interval`.
+    pub fn spawn(client: reqwest::Client, status_url: String, interval: Duration) -> Self {
+        let tracker = Self::new(interval * MAX_AGE_POLL_INTERVALS);
+
+        let heads = tracker.heads.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            loop {
+                interval.tick().await;
+
+                // Keep serving the previous heads if polling fails, until
+                // they are too old
+                match poll(&client, &status_url).await {
+                    Ok(polled) => {
+                        debug!(heads = ?polled, "Polled block heads");
+                        *heads.write().unwrap() = Some((polled, Instant::now()));
+                    }
+                    Err(e) => warn!(error = %e, "Failed to poll block heads"),
+                }
+            }
+        });
+
+        tracker
+    }
+
+    /// Result of a status query from the tracked block heads, if the query
+    /// selects nothing but block head fields without arguments or directives,
+    /// and the heads were polled recently.
+    pub fn serve(&self, document: &q::Document<'_, String>) -> Option<Value> {
+        let [q::Definition::Operation(operation)] = document.definitions.as_slice() else {
+            return None;
+        };
+        let selection_set = match operation {
+            q::OperationDefinition::SelectionSet(selection_set) => selection_set,
+            q::OperationDefinition::Query(query)
+                if query.variable_definitions.is_empty() && query.directives.is_empty() =>
+            {
+                &query.selection_set
+            }
+            _ => return None,
+        };
+        if !simple_fields(selection_set)?
+            .iter()
+            .all(|field| HEAD_FIELDS.contains(&field.name.as_str()))
+        {
+            return None;
+        }
+
+        let heads = self.heads.read().unwrap();
+        let (heads, polled_at) = heads.as_ref()?;
+        if polled_at.elapsed() > self.max_age {
+            return None;
+        }
+        select(&Value::Object(heads.clone()), selection_set)
+    }
+}
+
+async fn poll(client: &reqwest::Client, status_url: &str) -> anyhow::Result<Map<String, Value>> {
+    let response: Value = client
+        .post(status_url)
+        .json(&json!({ "query": HEAD_QUERY }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    match response.get("data") {
+        Some(Value::Object(data)) => Ok(data.clone()),
+        _ => Err(anyhow::anyhow!(
+            "Unexpected block heads response: {response}"
+        )),
+    }
+}
+
+/// Fields of a selection set, unless it has fragments, arguments or
+/// directives.
+fn simple_fields<'d, 'a>(
+    selection_set: &'d q::SelectionSet<'a, String>,
+) -> Option<Vec<&'d q::Field<'a, String>>> {
+    selection_set
+        .items
+        .iter()
+        .map(|item| match item {
+            q::Selection::Field(field)
+                if field.arguments.is_empty() && field.directives.is_empty() =>
+            {
+                Some(field)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The part of `value` selected by the selection set, if `value` has all the
+/// selected fields.
+fn select(value: &Value, selection_set: &q::SelectionSet<'_, String>) -> Option<Value> {
+    if selection_set.items.is_empty() {
+        return Some(value.clone());
+    }
+    match value {
+        Value::Null => Some(Value::Null),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| select(value, selection_set))
+            .collect::<Option<Vec<_>>>()
+            .map(Value::Array),
+        Value::Object(object) => {
+            let mut selected = Map::new();
+            for field in simple_fields(selection_set)? {
+                let value = select(object.get(&field.name)?, &field.selection_set)?;
+                selected.insert(field.alias.as_ref().unwrap_or(&field.name).clone(), value);
+            }
+            Some(Value::Object(selected))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker_polled_at(polled_at: Instant) -> HeadTracker {
+        let tracker = HeadTracker::new(Duration::from_secs(60));
+        *tracker.heads.write().unwrap() = Some((
+            json!({
+                "earliestBlock": { "number": "1", "hash": "0x01" },
+                "latestBlock": { "number": "100", "hash": "0x64" },
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+            polled_at,
+        ));
+        tracker
+    }
+
+    fn tracker() -> HeadTracker {
+        tracker_polled_at(Instant::now())
+    }
+
+    fn serve(tracker: &HeadTracker, query: &str) -> Option<Value> {
+        tracker.serve(&q::parse_query(query).unwrap())
+    }
+
+    #[test]
+    fn test_head_fields_are_served() {
+        let tracker = tracker();
+        assert_eq!(
+            serve(
+                &tracker,
+                "{ latestBlock { number } head: earliestBlock { hash } }"
+            ),
+            Some(json!({
+                "latestBlock": { "number": "100" },
+                "head": { "hash": "0x01" },
+            }))
+        );
+        assert_eq!(
+            serve(&tracker, "query Heads { latestBlock { hash } }"),
+            Some(json!({ "latestBlock": { "hash": "0x64" } }))
+        );
+    }
+
+    #[test]
+    fn test_other_queries_are_not_served() {
+        let tracker = tracker();
+        // Not polled yet
+        assert_eq!(
+            serve(
+                &HeadTracker::new(Duration::from_secs(60)),
+                "{ latestBlock { number } }"
+            ),
+            None
+        );
+        // Other root fields
+        assert_eq!(
+            serve(&tracker, "{ latestBlock { number } chains { network } }"),
+            None
+        );
+        // Fields that aren't tracked
+        assert_eq!(serve(&tracker, "{ latestBlock { timestamp } }"), None);
+        // Directives and fragments
+        assert_eq!(
+            serve(
+                &tracker,
+                "query($skip: Boolean!) { latestBlock @skip(if: $skip) { number } }"
+            ),
+            None
+        );
+        assert_eq!(
+            serve(
+                &tracker,
+                "{ ...Heads } fragment Heads on Query { latestBlock { number } }"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stale_heads_are_not_served() {
+        let tracker = tracker_polled_at(Instant::now() - Duration::from_secs(61));
+        assert_eq!(serve(&tracker, "{ latestBlock { number } }"), None);
+    }
+}
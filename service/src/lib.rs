@@ -1,9 +1,27 @@
 // Copyright 2023-, GraphOps and Semiotic Labs.
 // SPDX-License-Identifier: Apache-2.0
 
+mod attestation_overrides;
 mod cli;
 mod config;
+mod cost_model_files;
 mod database;
 mod error;
+mod features;
+mod head_tracker;
+mod logging;
+mod metrics;
+mod persisted_queries;
+mod query_cost;
+mod query_validation;
+mod registry;
+mod request_queue;
 mod routes;
+mod server_timing;
 pub mod service;
+mod statsd;
+mod status_cache;
+#[cfg(test)]
+mod test_utils;
+mod transforms;
+mod upstream;
@@ -0,0 +1,205 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde_json::Value;
+
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+/// Set up logging to stdout, filtered by `RUST_LOG`, and optionally export
+/// the timings of all spans to a Chrome trace file. The trace is complete once
+/// the returned guard is dropped.
+pub fn init_tracing(trace_export_file: Option<&Path>) -> Option<FlushGuard> {
+    let (chrome, guard) = match trace_export_file {
+        Some(path) => {
+            let (layer, guard) = chrome_layer(path);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_filter(EnvFilter::from_default_env()))
+        .with(chrome)
+        .init();
+    guard
+}
+
+pub fn chrome_layer<S>(path: &Path) -> (impl Layer<S>, FlushGuard)
+where
+    S: tracing::Subscriber
+        + for<'span> tracing_subscriber::registry::LookupSpan<'span>
+        + Send
+        + Sync,
+{
+    let (layer, guard) = ChromeLayerBuilder::new()
+        .file(path)
+        .include_args(true)
+        .build();
+    (layer.with_filter(LevelFilter::INFO), guard)
+}
+
+/// Number of queries whose last response is remembered by default.
+const MAX_TRACKED_QUERIES: usize = 1000;
+
+/// Remembers the last response logged for each query, so unchanged responses
+/// to repeated queries (e.g. from polling) aren't logged again. Queries are
+/// identified by a key that should include their variables, like
+/// `StatusCache::key`. Only the most recently seen queries are remembered, by
+/// hash.
+pub struct ResponseDiffLog {
+    max_entries: usize,
+    last_responses: Mutex<LastResponses>,
+}
+
+#[derive(Default)]
+struct LastResponses {
+    /// Response hash and last use of each query hash
+    entries: HashMap<u64, (u64, u64)>,
+    uses: u64,
+}
+
+impl Default for ResponseDiffLog {
+    fn default() -> Self {
+        Self::new(MAX_TRACKED_QUERIES)
+    }
+}
+
+impl ResponseDiffLog {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            last_responses: Mutex::new(LastResponses::default()),
+        }
+    }
+
+    /// Whether `response` differs from the last response to the query
+    /// identified by `key`.
+    pub fn changed(&self, key: &str, response: &Value) -> bool {
+        let query = hash(key);
+        let response = hash(&response.to_string());
+
+        let mut last_responses = self.last_responses.lock().unwrap();
+        last_responses.uses += 1;
+        let uses = last_responses.uses;
+        let entries = &mut last_responses.entries;
+        if entries.len() >= self.max_entries && !entries.contains_key(&query) {
+            let least_recent = entries
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(query, _)| *query);
+            if let Some(least_recent) = least_recent {
+                entries.remove(&least_recent);
+            }
+        }
+        entries
+            .insert(query, (response, uses))
+            .map_or(true, |(last_response, _)| last_response != response)
+    }
+}
+
+fn hash(value: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Prepare a query for logging, optionally collapsing all whitespace runs
+/// (including newlines) into single spaces so the query fits on one line.
+pub fn loggable_query(query: &str, collapse_whitespace: bool) -> Cow<'_, str> {
+    if collapse_whitespace {
+        Cow::Owned(query.split_whitespace().collect::<Vec<_>>().join(" "))
+    } else {
+        Cow::Borrowed(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graphql::graphql_parser::query as q;
+    use serde_json::json;
+
+    use super::*;
+    use crate::status_cache::StatusCache;
+
+    const QUERY: &str = r#"
+        query {
+            indexingStatuses {
+                subgraph
+                health
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_loggable_query_collapses_whitespace() {
+        let query = loggable_query(QUERY, true);
+        assert!(!query.contains('\n'));
+        assert_eq!(query, "query { indexingStatuses { subgraph health } }");
+    }
+
+    #[test]
+    fn test_loggable_query_untouched_when_disabled() {
+        assert_eq!(loggable_query(QUERY, false), QUERY);
+    }
+
+    #[test]
+    fn test_identical_responses_are_logged_once() {
+        let log = ResponseDiffLog::default();
+        let synced = json!({ "data": { "indexingStatuses": [{ "synced": true }] } });
+        let syncing = json!({ "data": { "indexingStatuses": [{ "synced": false }] } });
+
+        assert!(log.changed(QUERY, &syncing));
+        assert!(!log.changed(QUERY, &syncing));
+        assert!(log.changed(QUERY, &synced));
+        assert!(!log.changed(QUERY, &synced));
+        // Each query is tracked on its own
+        assert!(log.changed("{ chains { network } }", &synced));
+    }
+
+    #[test]
+    fn test_queries_with_different_variables_are_tracked_separately() {
+        let document = q::parse_query::<String>(
+            "query($ids: [String!]) { indexingStatuses(subgraphs: $ids) { synced } }",
+        )
+        .unwrap();
+        let key = |id: &str| {
+            let variables = async_graphql::Variables::from_json(json!({ "ids": [id] }));
+            StatusCache::key(&document, None, &variables)
+        };
+        let log = ResponseDiffLog::default();
+        let synced = json!({ "data": { "indexingStatuses": [{ "synced": true }] } });
+        let syncing = json!({ "data": { "indexingStatuses": [{ "synced": false }] } });
+
+        assert!(log.changed(&key("QmA"), &synced));
+        assert!(log.changed(&key("QmB"), &syncing));
+        // Alternating between the variable sets doesn't look like a change
+        assert!(!log.changed(&key("QmA"), &synced));
+        assert!(!log.changed(&key("QmB"), &syncing));
+        assert!(log.changed(&key("QmB"), &synced));
+    }
+
+    #[test]
+    fn test_least_recent_query_is_forgotten() {
+        let log = ResponseDiffLog::new(2);
+        let response = json!({ "data": {} });
+
+        assert!(log.changed("first", &response));
+        assert!(log.changed("second", &response));
+        assert!(!log.changed("first", &response));
+        // Makes room by forgetting `second`, which was seen least recently
+        assert!(log.changed("third", &response));
+        assert!(!log.changed("first", &response));
+        assert!(log.changed("second", &response));
+    }
+}
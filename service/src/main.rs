@@ -7,7 +7,6 @@ use service::service::run;
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    tracing_subscriber::fmt::init();
     if let Err(e) = run().await {
         tracing::error!("Indexer service error: {e}");
         return ExitCode::from(1);
@@ -0,0 +1,263 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    error::Error,
+    sync::Once,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use indexer_common::indexer_service::http::register_or_warn;
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, Gauge, Histogram, HistogramVec, IntCounter,
+    IntCounterVec,
+};
+use reqwest::StatusCode;
+use tracing::info;
+
+use crate::query_validation::QueryComplexity;
+
+// Metrics that fail to register, e.g. because metrics with the same name exist
+// already, are still recorded but not exported
+lazy_static! {
+    /// Status queries whose upstream call took longer than the configured SLO
+    pub static ref STATUS_SLO_VIOLATIONS: IntCounter = register_or_warn(
+        IntCounter::new(
+            "subgraph_status_slo_violations_total",
+            "Status queries that exceeded the upstream response time SLO"
+        )
+        .expect("Valid metric name")
+    );
+
+    /// When graph-node was first reached successfully after startup
+    pub static ref FIRST_UPSTREAM_SUCCESS_TIMESTAMP: Gauge = register_or_warn(
+        Gauge::new(
+            "subgraph_first_upstream_success_timestamp",
+            "Unix time in seconds of the first successful request to graph-node"
+        )
+        .expect("Valid metric name")
+    );
+
+    pub static ref QUERY_DEPTH: Histogram = register_or_warn(
+        Histogram::with_opts(histogram_opts!(
+            "subgraph_query_depth",
+            "Deepest field nesting of forwarded queries",
+            exponential_buckets(1.0, 2.0, 7).unwrap()
+        ))
+        .expect("Valid metric name")
+    );
+
+    pub static ref QUERY_FIELDS: Histogram = register_or_warn(
+        Histogram::with_opts(histogram_opts!(
+            "subgraph_query_fields",
+            "Number of fields in forwarded queries",
+            exponential_buckets(1.0, 2.0, 12).unwrap()
+        ))
+        .expect("Valid metric name")
+    );
+
+    pub static ref QUERY_ALIASES: Histogram = register_or_warn(
+        Histogram::with_opts(histogram_opts!(
+            "subgraph_query_aliases",
+            "Number of aliased fields in forwarded queries",
+            exponential_buckets(1.0, 2.0, 10).unwrap()
+        ))
+        .expect("Valid metric name")
+    );
+
+    /// Time spent waiting for graph-node, by endpoint (`query` or `status`)
+    pub static ref UPSTREAM_LATENCY: HistogramVec = register_or_warn(
+        HistogramVec::new(
+            histogram_opts!(
+                "subgraph_upstream_request_duration_seconds",
+                "Time until graph-node answered a request, including retries"
+            ),
+            &["endpoint"]
+        )
+        .expect("Valid metric name")
+    );
+
+    pub static ref UPSTREAM_ERRORS: IntCounterVec = register_or_warn(
+        IntCounterVec::new(
+            opts!(
+                "subgraph_upstream_errors_total",
+                "Failed requests to graph-node, by endpoint and kind (timeout, connection, 4xx, 5xx, other)"
+            ),
+            &["endpoint", "kind"]
+        )
+        .expect("Valid metric name")
+    );
+
+    pub static ref QUERY_RESPONSES: IntCounterVec = register_or_warn(
+        IntCounterVec::new(
+            opts!(
+                "subgraph_query_responses_total",
+                "Query responses forwarded from graph-node, by whether they are attestable"
+            ),
+            &["attestable"]
+        )
+        .expect("Valid metric name")
+    );
+}
+
+/// Register the metrics of the subgraph service, so that they are exported
+/// before they are first recorded.
+pub fn register_metrics() {
+    lazy_static::initialize(&STATUS_SLO_VIOLATIONS);
+    lazy_static::initialize(&FIRST_UPSTREAM_SUCCESS_TIMESTAMP);
+    lazy_static::initialize(&QUERY_DEPTH);
+    lazy_static::initialize(&QUERY_FIELDS);
+    lazy_static::initialize(&QUERY_ALIASES);
+    lazy_static::initialize(&UPSTREAM_LATENCY);
+    lazy_static::initialize(&UPSTREAM_ERRORS);
+    lazy_static::initialize(&QUERY_RESPONSES);
+}
+
+static FIRST_UPSTREAM_SUCCESS: Once = Once::new();
+
+/// Record a successful request to graph-node. Only the first one after
+/// startup is logged and sets `FIRST_UPSTREAM_SUCCESS_TIMESTAMP`.
+pub fn record_upstream_success() {
+    FIRST_UPSTREAM_SUCCESS.call_once(|| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        FIRST_UPSTREAM_SUCCESS_TIMESTAMP.set(now.as_secs_f64());
+        info!("Reached graph-node for the first time");
+    });
+}
+
+pub fn record_upstream_latency(endpoint: &str, latency: Duration) {
+    UPSTREAM_LATENCY
+        .with_label_values(&[endpoint])
+        .observe(latency.as_secs_f64());
+}
+
+/// Count an upstream error of the given kind, see [`upstream_error_kind`]
+/// and [`upstream_status_kind`].
+pub fn record_upstream_error(endpoint: &str, kind: &str) {
+    UPSTREAM_ERRORS.with_label_values(&[endpoint, kind]).inc();
+}
+
+/// Classify a failed upstream request by the first `reqwest` error in its
+/// source chain.
+pub fn upstream_error_kind(error: &(dyn Error + 'static)) -> &'static str {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+            return if error.is_timeout() {
+                "timeout"
+            } else if error.is_connect() {
+                "connection"
+            } else {
+                error
+                    .status()
+                    .and_then(upstream_status_kind)
+                    .unwrap_or("other")
+            };
+        }
+        source = error.source();
+    }
+    "other"
+}
+
+/// Classify an upstream response status, `None` if it isn't an error.
+pub fn upstream_status_kind(status: StatusCode) -> Option<&'static str> {
+    if status.is_client_error() {
+        Some("4xx")
+    } else if status.is_server_error() {
+        Some("5xx")
+    } else {
+        None
+    }
+}
+
+pub fn record_query_response(attestable: bool) {
+    QUERY_RESPONSES
+        .with_label_values(&[if attestable { "true" } else { "false" }])
+        .inc();
+}
+
+pub fn record_query_complexity(complexity: &QueryComplexity) {
+    QUERY_DEPTH.observe(complexity.depth as f64);
+    QUERY_FIELDS.observe(complexity.fields as f64);
+    QUERY_ALIASES.observe(complexity.aliases as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_duplicate_registration_is_not_fatal() {
+        // Whichever registration comes second fails, here or in `register_metrics`
+        let duplicate = IntCounterVec::new(
+            opts!("subgraph_query_responses_total", "Duplicate"),
+            &["attestable"],
+        )
+        .unwrap();
+        let _ = prometheus::register(Box::new(duplicate));
+        let duplicate = register_or_warn(
+            IntCounter::new("subgraph_status_slo_violations_total", "Duplicate").unwrap(),
+        );
+
+        register_metrics();
+        let responses = QUERY_RESPONSES.with_label_values(&["true"]).get();
+        record_query_response(true);
+        assert_eq!(
+            QUERY_RESPONSES.with_label_values(&["true"]).get(),
+            responses + 1
+        );
+        duplicate.inc();
+        assert_eq!(duplicate.get(), 1);
+    }
+
+    #[test]
+    fn test_first_upstream_success_is_recorded_once() {
+        record_upstream_success();
+        let first = FIRST_UPSTREAM_SUCCESS_TIMESTAMP.get();
+        assert!(first > 0.0);
+
+        std::thread::sleep(Duration::from_millis(10));
+        record_upstream_success();
+        assert_eq!(FIRST_UPSTREAM_SUCCESS_TIMESTAMP.get(), first);
+    }
+
+    #[test]
+    fn test_query_complexity_is_recorded() {
+        let (depth, fields, aliases) = (
+            QUERY_DEPTH.get_sample_count(),
+            QUERY_FIELDS.get_sample_sum(),
+            QUERY_ALIASES.get_sample_sum(),
+        );
+
+        record_query_complexity(&QueryComplexity {
+            depth: 2,
+            fields: 5,
+            aliases: 1,
+        });
+
+        assert!(QUERY_DEPTH.get_sample_count() > depth);
+        assert!(QUERY_FIELDS.get_sample_sum() >= fields + 5.0);
+        assert!(QUERY_ALIASES.get_sample_sum() >= aliases + 1.0);
+    }
+
+    #[test]
+    fn test_upstream_status_kind() {
+        assert_eq!(upstream_status_kind(StatusCode::OK), None);
+        assert_eq!(upstream_status_kind(StatusCode::NOT_FOUND), Some("4xx"));
+        assert_eq!(upstream_status_kind(StatusCode::BAD_GATEWAY), Some("5xx"));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_kind() {
+        // Nothing listens on port 1
+        let error = reqwest::get("http://127.0.0.1:1").await.unwrap_err();
+        assert_eq!(upstream_error_kind(&error), "connection");
+
+        let error = anyhow::Error::from(error);
+        assert_eq!(upstream_error_kind(error.as_ref()), "connection");
+        assert_eq!(upstream_error_kind(&std::fmt::Error), "other");
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{collections::HashMap, sync::RwLock};
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::error::SubgraphServiceError;
+
+/// Queries sent with the Automatic Persisted Queries (APQ) extension, by
+/// their SHA-256 hash, so that clients can send just the hash afterwards.
+pub struct PersistedQueries {
+    max_entries: usize,
+    queries: RwLock<HashMap<String, String>>,
+}
+
+impl PersistedQueries {
+    /// A `max_entries` of zero disables persisted queries; requests are then
+    /// forwarded as they are.
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            queries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Expand a request carrying only the hash of a persisted query to the
+    /// full query, and remember queries sent along with their hash.
+    pub fn resolve(&self, request: &mut Value) -> Result<(), SubgraphServiceError> {
+        if self.max_entries == 0 {
+            return Ok(());
+        }
+        let Some(hash) = request
+            .pointer("/extensions/persistedQuery/sha256Hash")
+            .and_then(Value::as_str)
+            .map(str::to_lowercase)
+        else {
+            return Ok(());
+        };
+
+        match request.get("query").and_then(Value::as_str) {
+            Some(query) => {
+                if hex::encode(Sha256::digest(query)) != hash {
+                    return Err(SubgraphServiceError::PersistedQueryHashMismatch);
+                }
+                let mut queries = self.queries.write().unwrap();
+                if queries.len() >= self.max_entries && !queries.contains_key(&hash) {
+                    if let Some(evicted) = queries.keys().next().cloned() {
+                        queries.remove(&evicted);
+                    }
+                }
+                queries.insert(hash, query.to_string());
+            }
+            None => {
+                let query = self
+                    .queries
+                    .read()
+                    .unwrap()
+                    .get(&hash)
+                    .cloned()
+                    .ok_or(SubgraphServiceError::PersistedQueryNotFound)?;
+                request["query"] = Value::String(query);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const QUERY: &str = "{ tokens { id } }";
+
+    fn persisted(query: Option<&str>, hash: &str) -> Value {
+        let mut request = json!({
+            "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } }
+        });
+        if let Some(query) = query {
+            request["query"] = json!(query);
+        }
+        request
+    }
+
+    #[test]
+    fn test_hash_only_request_is_expanded() {
+        let queries = PersistedQueries::new(10);
+        let hash = hex::encode(Sha256::digest(QUERY));
+
+        let mut request = persisted(None, &hash);
+        assert!(matches!(
+            queries.resolve(&mut request),
+            Err(SubgraphServiceError::PersistedQueryNotFound)
+        ));
+
+        queries.resolve(&mut persisted(Some(QUERY), &hash)).unwrap();
+        queries.resolve(&mut request).unwrap();
+        assert_eq!(request["query"], QUERY);
+    }
+
+    #[test]
+    fn test_hash_mismatch_is_rejected() {
+        let queries = PersistedQueries::new(10);
+        let hash = hex::encode(Sha256::digest("{ other }"));
+
+        assert!(matches!(
+            queries.resolve(&mut persisted(Some(QUERY), &hash)),
+            Err(SubgraphServiceError::PersistedQueryHashMismatch)
+        ));
+        assert!(queries.resolve(&mut persisted(None, &hash)).is_err());
+    }
+
+    #[test]
+    fn test_entries_are_bounded() {
+        let queries = PersistedQueries::new(1);
+        for query in ["{ a }", "{ b }"] {
+            let hash = hex::encode(Sha256::digest(query));
+            queries.resolve(&mut persisted(Some(query), &hash)).unwrap();
+        }
+        assert_eq!(queries.queries.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let queries = PersistedQueries::new(0);
+        let mut request = persisted(None, "abc");
+        queries.resolve(&mut request).unwrap();
+        assert!(request.get("query").is_none());
+    }
+}
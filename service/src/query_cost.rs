@@ -0,0 +1,270 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::anyhow;
+use axum::http::HeaderMap;
+use bigdecimal::BigDecimal;
+use cost_model::CostModel as AgoraCostModel;
+use serde_json::Value;
+use thegraph::types::DeploymentId;
+
+use crate::{database::CostModel, error::SubgraphServiceError};
+
+/// Header gateways use to tell us the maximum they are willing to pay for a
+/// query, in GRT.
+pub const MAX_QUERY_COST_HEADER: &str = "x-max-query-cost";
+
+/// Parse the `X-Max-Query-Cost` header into GRT wei, if present.
+pub fn max_query_cost(headers: &HeaderMap) -> Result<Option<BigDecimal>, SubgraphServiceError> {
+    headers
+        .get(MAX_QUERY_COST_HEADER)
+        .map(|value| {
+            let value = value
+                .to_str()
+                .map_err(|e| SubgraphServiceError::InvalidMaxQueryCost(e.into()))?;
+            let grt = BigDecimal::from_str(value.trim())
+                .map_err(|e| SubgraphServiceError::InvalidMaxQueryCost(e.into()))?;
+            if grt < 0.into() {
+                return Err(SubgraphServiceError::InvalidMaxQueryCost(anyhow!(
+                    "maximum query cost must not be negative"
+                )));
+            }
+            Ok(grt * BigDecimal::from(10u64.pow(18)))
+        })
+        .transpose()
+}
+
+/// Compiled cost models by deployment, so that they are only compiled again
+/// when the model or its variables change.
+#[derive(Default)]
+pub struct CostModelCache {
+    models: Mutex<HashMap<DeploymentId, CompiledCostModel>>,
+}
+
+struct CompiledCostModel {
+    source: String,
+    globals: String,
+    model: Arc<AgoraCostModel>,
+}
+
+impl CostModelCache {
+    /// The compiled cost model of the deployment, or `None` if it has none.
+    pub fn compile(
+        &self,
+        model: &CostModel,
+    ) -> Result<Option<Arc<AgoraCostModel>>, SubgraphServiceError> {
+        let Some(source) = model.model.as_ref() else {
+            return Ok(None);
+        };
+        let globals = model
+            .variables
+            .as_ref()
+            .map(Value::to_string)
+            .unwrap_or_else(|| "{}".to_string());
+
+        if let Some(compiled) = self.models.lock().unwrap().get(&model.deployment) {
+            if &compiled.source == source && compiled.globals == globals {
+                return Ok(Some(compiled.model.clone()));
+            }
+        }
+
+        let compiled = Arc::new(
+            AgoraCostModel::compile(source.as_str(), &globals)
+                .map_err(|e| SubgraphServiceError::InvalidCostModel(anyhow!("{:?}", e)))?,
+        );
+        self.models.lock().unwrap().insert(
+            model.deployment,
+            CompiledCostModel {
+                source: source.clone(),
+                globals,
+                model: compiled.clone(),
+            },
+        );
+        Ok(Some(compiled))
+    }
+}
+
+/// Estimate the cost of a query in GRT wei using the deployment's cost model,
+/// scaled by the multiplier configured for the query's operation name.
+///
+/// Returns `None` if the deployment has no cost model, in which case the
+/// query cannot be priced.
+pub fn estimate_query_cost(
+    cache: &CostModelCache,
+    model: &CostModel,
+    request: &Value,
+    multipliers: &HashMap<String, f64>,
+) -> Result<Option<BigDecimal>, SubgraphServiceError> {
+    let Some(model) = cache.compile(model)? else {
+        return Ok(None);
+    };
+
+    let query = request
+        .get("query")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let variables = request
+        .get("variables")
+        .filter(|variables| !variables.is_null())
+        .map(Value::to_string)
+        .unwrap_or_else(|| "{}".to_string());
+
+    let cost = model
+        .cost(query, &variables)
+        .map_err(|e| SubgraphServiceError::QueryCostEstimationError(anyhow!("{:?}", e)))?;
+
+    // Go through the decimal representation to avoid depending on the exact
+    // big integer types used by the cost model
+    let cost = BigDecimal::from_str(&cost.to_string())
+        .map_err(|e| SubgraphServiceError::QueryCostEstimationError(e.into()))?;
+
+    let multiplier = request
+        .get("operationName")
+        .and_then(Value::as_str)
+        .and_then(|operation| multipliers.get(operation));
+    match multiplier {
+        Some(multiplier) => {
+            let multiplier = BigDecimal::from_str(&multiplier.to_string())
+                .map_err(|e| SubgraphServiceError::QueryCostEstimationError(e.into()))?;
+            Ok(Some((cost * multiplier).with_scale(0)))
+        }
+        None => Ok(Some(cost)),
+    }
+}
+
+/// Reject the query if its estimated cost exceeds the maximum the client is
+/// willing to pay.
+pub fn check_query_cost(
+    cache: &CostModelCache,
+    model: &CostModel,
+    request: &Value,
+    multipliers: &HashMap<String, f64>,
+    max_cost: &BigDecimal,
+) -> Result<(), SubgraphServiceError> {
+    match estimate_query_cost(cache, model, request, multipliers)? {
+        Some(cost) if &cost > max_cost => Err(SubgraphServiceError::QueryCostExceeded {
+            cost,
+            max_cost: max_cost.clone(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    use super::*;
+
+    fn cost_model() -> CostModel {
+        CostModel {
+            deployment: DeploymentId::from_str("Qmb5Ysp5oCUXhLA8NmxmYKDAX2nCMnh7Vvb5uffb9n5vss")
+                .unwrap(),
+            model: Some("default => 0.00025;".to_string()),
+            variables: None,
+        }
+    }
+
+    fn max_cost(grt: &'static str) -> BigDecimal {
+        let mut headers = HeaderMap::new();
+        headers.insert(MAX_QUERY_COST_HEADER, HeaderValue::from_static(grt));
+        max_query_cost(&headers).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_max_query_cost_header() {
+        assert!(max_query_cost(&HeaderMap::new()).unwrap().is_none());
+        assert_eq!(
+            max_cost("0.001"),
+            BigDecimal::from(1_000_000_000_000_000u64)
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(MAX_QUERY_COST_HEADER, HeaderValue::from_static("a lot"));
+        assert!(max_query_cost(&headers).is_err());
+    }
+
+    #[test]
+    fn test_query_within_budget() {
+        let request = json!({ "query": "{ a { b } }" });
+        assert!(check_query_cost(
+            &CostModelCache::default(),
+            &cost_model(),
+            &request,
+            &HashMap::new(),
+            &max_cost("0.001")
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_query_over_budget() {
+        let request = json!({ "query": "{ a { b } }" });
+        assert!(matches!(
+            check_query_cost(
+                &CostModelCache::default(),
+                &cost_model(),
+                &request,
+                &HashMap::new(),
+                &max_cost("0.0001")
+            ),
+            Err(SubgraphServiceError::QueryCostExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_operation_cost_multiplier() {
+        let multipliers = HashMap::from([("expensive".to_string(), 2.5)]);
+        let cache = CostModelCache::default();
+        let estimate = |request: Value| {
+            estimate_query_cost(&cache, &cost_model(), &request, &multipliers)
+                .unwrap()
+                .unwrap()
+        };
+
+        let base = estimate(json!({ "query": "{ a { b } }" }));
+        assert_eq!(base, BigDecimal::from(250_000_000_000_000u64));
+        assert_eq!(
+            estimate(json!({ "query": "query cheap { a { b } }", "operationName": "cheap" })),
+            base
+        );
+        assert_eq!(
+            estimate(json!({
+                "query": "query expensive { a { b } }",
+                "operationName": "expensive"
+            })),
+            BigDecimal::from(625_000_000_000_000u64)
+        );
+    }
+
+    #[test]
+    fn test_compiled_cost_models_are_cached() {
+        let cache = CostModelCache::default();
+        let mut model = cost_model();
+        let first = cache.compile(&model).unwrap().unwrap();
+        assert!(Arc::ptr_eq(
+            &first,
+            &cache.compile(&model).unwrap().unwrap()
+        ));
+
+        // Changing the model or its variables compiles it again
+        model.variables = Some(json!({ "price": 1 }));
+        let second = cache.compile(&model).unwrap().unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        model.model = Some("default => 0.0005;".to_string());
+        assert!(!Arc::ptr_eq(
+            &second,
+            &cache.compile(&model).unwrap().unwrap()
+        ));
+
+        model.model = None;
+        assert!(cache.compile(&model).unwrap().is_none());
+    }
+}
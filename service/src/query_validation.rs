@@ -0,0 +1,434 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use graphql::graphql_parser::query as q;
+use jsonschema::JSONSchema;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::error::SubgraphServiceError;
+
+lazy_static! {
+    static ref REQUEST_SCHEMA: JSONSchema = JSONSchema::compile(&json!({
+        "type": "object",
+        "required": ["query"],
+        "properties": {
+            "query": { "type": "string" },
+            "operationName": { "type": ["string", "null"] },
+            "variables": { "type": ["object", "null"] },
+            "extensions": { "type": ["object", "null"] }
+        }
+    }))
+    .expect("Valid request JSON schema");
+}
+
+/// Validate the shape of a GraphQL request body.
+pub fn validate_request_schema(request: &Value) -> Result<(), SubgraphServiceError> {
+    REQUEST_SCHEMA.validate(request).map_err(|errors| {
+        SubgraphServiceError::InvalidRequestSchema(
+            errors
+                .map(|e| format!("{}: {}", e.instance_path, e))
+                .collect(),
+        )
+    })
+}
+
+/// Request `extensions` that are accepted when unknown ones are rejected.
+const KNOWN_EXTENSIONS: &[&str] = &["persistedQuery"];
+
+/// Reject requests carrying `extensions` other than the known ones.
+pub fn reject_unknown_extensions(request: &Value) -> Result<(), SubgraphServiceError> {
+    let Some(extensions) = request.get("extensions").and_then(Value::as_object) else {
+        return Ok(());
+    };
+    let unknown: Vec<_> = extensions
+        .keys()
+        .filter(|key| !KNOWN_EXTENSIONS.contains(&key.as_str()))
+        .cloned()
+        .collect();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(SubgraphServiceError::UnknownExtensions(unknown))
+    }
+}
+
+/// Reject requests without a non-empty `query`, unless they carry an Automatic
+/// Persisted Query hash that is passed on to graph-node.
+pub fn require_query(request: &Value) -> Result<(), SubgraphServiceError> {
+    let has_query = request
+        .get("query")
+        .and_then(Value::as_str)
+        .is_some_and(|query| !query.trim().is_empty());
+    if has_query || request.pointer("/extensions/persistedQuery").is_some() {
+        Ok(())
+    } else {
+        Err(SubgraphServiceError::MissingQuery)
+    }
+}
+
+/// Hex-encoded SHA-256 of a query, normalized by formatting it canonically
+/// so that whitespace and comments don't affect it. Queries that don't parse
+/// are hashed as they are.
+pub fn query_hash(query: &str) -> String {
+    let normalized = match q::parse_query::<String>(query) {
+        Ok(document) => document.to_string(),
+        Err(_) => query.to_string(),
+    };
+    hex::encode(Sha256::digest(normalized))
+}
+
+pub fn parse_query(query: &str) -> Result<q::Document<'_, String>, SubgraphServiceError> {
+    q::parse_query(query).map_err(|e| SubgraphServiceError::InvalidQuery(e.into()))
+}
+
+/// All top-level selection sets of a document, i.e. those of its operations
+/// and fragment definitions.
+pub fn root_selection_sets<'a, 'd>(
+    document: &'d q::Document<'a, String>,
+) -> impl Iterator<Item = &'d q::SelectionSet<'a, String>> {
+    document.definitions.iter().filter_map(|def| match def {
+        q::Definition::Operation(op) => match op {
+            q::OperationDefinition::Query(query) => Some(&query.selection_set),
+            q::OperationDefinition::SelectionSet(selection_set) => Some(selection_set),
+            q::OperationDefinition::Mutation(mutation) => Some(&mutation.selection_set),
+            q::OperationDefinition::Subscription(subscription) => Some(&subscription.selection_set),
+        },
+        q::Definition::Fragment(fragment) => Some(&fragment.selection_set),
+    })
+}
+
+/// Call `visit` for every field in the selection set, at any depth, including
+/// fields inside inline fragments.
+pub fn visit_fields<'a, 'd>(
+    selection_set: &'d q::SelectionSet<'a, String>,
+    visit: &mut impl FnMut(&'d q::Field<'a, String>),
+) {
+    for item in &selection_set.items {
+        match item {
+            q::Selection::Field(field) => {
+                visit(field);
+                visit_fields(&field.selection_set, visit);
+            }
+            q::Selection::InlineFragment(fragment) => {
+                visit_fields(&fragment.selection_set, visit);
+            }
+            q::Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
+/// Whether an argument value contains inline literals. Enums and booleans
+/// don't count, as they don't add meaningful variety to queries.
+fn contains_literal(value: &q::Value<'_, String>) -> bool {
+    match value {
+        q::Value::Variable(_) | q::Value::Enum(_) | q::Value::Boolean(_) => false,
+        q::Value::List(values) => values.iter().any(contains_literal),
+        q::Value::Object(fields) => fields.values().any(contains_literal),
+        q::Value::Int(_) | q::Value::Float(_) | q::Value::String(_) | q::Value::Null => true,
+    }
+}
+
+/// Arguments passed as inline literals rather than variables, as
+/// `field.argument`.
+pub fn inline_literal_arguments(document: &q::Document<'_, String>) -> Vec<String> {
+    let mut arguments = Vec::new();
+    for selection_set in root_selection_sets(document) {
+        visit_fields(selection_set, &mut |field| {
+            arguments.extend(
+                field
+                    .arguments
+                    .iter()
+                    .filter(|(_, value)| contains_literal(value))
+                    .map(|(name, _)| format!("{}.{}", field.name, name)),
+            );
+        });
+    }
+    arguments
+}
+
+/// The field selected most often with identical arguments and sub-selections
+/// within a single selection set, e.g. through aliases, with its count.
+pub fn most_repeated_selection(document: &q::Document<'_, String>) -> Option<(String, usize)> {
+    let mut most_repeated = None;
+    for selection_set in root_selection_sets(document) {
+        find_repeated_selections(selection_set, &mut most_repeated);
+    }
+    most_repeated
+}
+
+fn find_repeated_selections(
+    selection_set: &q::SelectionSet<'_, String>,
+    most_repeated: &mut Option<(String, usize)>,
+) {
+    let mut counts = HashMap::<String, usize>::new();
+    for item in &selection_set.items {
+        match item {
+            q::Selection::Field(field) => {
+                // Aliases are the only way to repeat a field, so they don't
+                // make selections different
+                let mut unaliased = field.clone();
+                unaliased.alias = None;
+                let count = counts.entry(unaliased.to_string()).or_default();
+                *count += 1;
+                if !most_repeated
+                    .as_ref()
+                    .is_some_and(|(_, most)| *most >= *count)
+                {
+                    *most_repeated = Some((field.name.clone(), *count));
+                }
+                find_repeated_selections(&field.selection_set, most_repeated);
+            }
+            q::Selection::InlineFragment(fragment) => {
+                find_repeated_selections(&fragment.selection_set, most_repeated);
+            }
+            q::Selection::FragmentSpread(_) => {}
+        }
+    }
+}
+
+/// Reject queries repeating an identical selection more than `max` times.
+pub fn check_repeated_selections(
+    document: &q::Document<'_, String>,
+    max: usize,
+) -> Result<(), SubgraphServiceError> {
+    match most_repeated_selection(document) {
+        Some((field, count)) if count > max => {
+            Err(SubgraphServiceError::RepetitiveQuery { field, count, max })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Size measures of a query, used to track how demanding queries are.
+#[derive(Debug, Default, PartialEq)]
+pub struct QueryComplexity {
+    /// Deepest nesting of fields, counting root fields as 1
+    pub depth: usize,
+    pub fields: usize,
+    pub aliases: usize,
+}
+
+pub fn complexity(document: &q::Document<'_, String>) -> QueryComplexity {
+    let mut complexity = QueryComplexity::default();
+    for selection_set in root_selection_sets(document) {
+        complexity.depth = complexity.depth.max(depth(selection_set));
+        visit_fields(selection_set, &mut |field| {
+            complexity.fields += 1;
+            if field.alias.is_some() {
+                complexity.aliases += 1;
+            }
+        });
+    }
+    complexity
+}
+
+fn depth(selection_set: &q::SelectionSet<'_, String>) -> usize {
+    selection_set
+        .items
+        .iter()
+        .map(|item| match item {
+            q::Selection::Field(field) => 1 + depth(&field.selection_set),
+            q::Selection::InlineFragment(fragment) => depth(&fragment.selection_set),
+            q::Selection::FragmentSpread(_) => 0,
+        })
+        .max()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_hash_ignores_formatting() {
+        let hash = query_hash("{ tokens(first: 10) { id } }");
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            hash,
+            query_hash("query {\n  tokens(first: 10) {\n    id # the id\n  }\n}")
+        );
+        assert_ne!(hash, query_hash("{ tokens(first: 5) { id } }"));
+    }
+
+    #[test]
+    fn test_valid_request_schema() {
+        assert!(validate_request_schema(&json!({ "query": "{ a }" })).is_ok());
+        assert!(validate_request_schema(&json!({
+            "query": "query q($id: ID!) { a(id: $id) }",
+            "operationName": "q",
+            "variables": { "id": "1" },
+            "extensions": null
+        }))
+        .is_ok());
+    }
+
+    #[test]
+    fn test_invalid_request_schema() {
+        for request in [
+            json!("{ a }"),
+            json!({ "variables": {} }),
+            json!({ "query": 1 }),
+            json!({ "query": "{ a }", "variables": [] }),
+        ] {
+            assert!(matches!(
+                validate_request_schema(&request),
+                Err(SubgraphServiceError::InvalidRequestSchema(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_known_extensions() {
+        for request in [
+            json!({ "query": "{ a }" }),
+            json!({ "query": "{ a }", "extensions": null }),
+            json!({
+                "query": "{ a }",
+                "extensions": { "persistedQuery": { "version": 1, "sha256Hash": "abc" } }
+            }),
+        ] {
+            assert!(reject_unknown_extensions(&request).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_unknown_extensions() {
+        let request = json!({
+            "query": "{ a }",
+            "extensions": { "persistedQuery": {}, "tracing": true }
+        });
+        assert!(matches!(
+            reject_unknown_extensions(&request),
+            Err(SubgraphServiceError::UnknownExtensions(keys)) if keys == vec!["tracing"]
+        ));
+    }
+
+    #[test]
+    fn test_require_query() {
+        assert!(require_query(&json!({ "query": "{ a }" })).is_ok());
+        assert!(matches!(
+            require_query(&json!({ "variables": {} })),
+            Err(SubgraphServiceError::MissingQuery)
+        ));
+        assert!(matches!(
+            require_query(&json!({ "query": " \n " })),
+            Err(SubgraphServiceError::MissingQuery)
+        ));
+        assert!(matches!(
+            require_query(&json!({ "query": null })),
+            Err(SubgraphServiceError::MissingQuery)
+        ));
+
+        // Persisted queries are expanded by graph-node if not by us
+        let persisted_query = json!({
+            "extensions": { "persistedQuery": { "version": 1, "sha256Hash": "abc" } }
+        });
+        assert!(require_query(&persisted_query).is_ok());
+    }
+
+    #[test]
+    fn test_query_with_variables_only() {
+        let document = parse_query(
+            r#"
+            query things($id: ID!, $first: Int) {
+                things(where: { id: $id }, first: $first, orderBy: name, desc: true) {
+                    id
+                    ... on Thing { owners(first: $first) { id } }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        assert!(inline_literal_arguments(&document).is_empty());
+    }
+
+    #[test]
+    fn test_query_with_inline_literals() {
+        let document = parse_query(
+            r#"
+            {
+                things(first: 10, orderBy: name) {
+                    owners(where: { name: "alice" }) { id }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            inline_literal_arguments(&document),
+            vec!["things.first".to_string(), "owners.where".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_repetitive_query() {
+        let document = parse_query(
+            r#"
+            {
+                a: things(first: 1000) { id owners { id } }
+                b: things(first: 1000) { id owners { id } }
+                c: things(first: 1000) { id owners { id } }
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            most_repeated_selection(&document),
+            Some(("things".to_string(), 3))
+        );
+        assert!(check_repeated_selections(&document, 3).is_ok());
+        assert!(matches!(
+            check_repeated_selections(&document, 2),
+            Err(SubgraphServiceError::RepetitiveQuery {
+                count: 3,
+                max: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_normal_query_is_not_repetitive() {
+        let document = parse_query(
+            r#"
+            {
+                first: things(first: 10) { id }
+                last: things(last: 10) { id }
+                more: things(first: 10) { id name }
+                owners { id }
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            most_repeated_selection(&document),
+            Some(("things".to_string(), 1))
+        );
+        assert!(check_repeated_selections(&document, 1).is_ok());
+    }
+
+    #[test]
+    fn test_query_complexity() {
+        let document = parse_query(
+            r#"
+            {
+                first: things(first: 10) { id owners { id } }
+                last: things(last: 10) { id ... on Thing { name } }
+            }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            complexity(&document),
+            QueryComplexity {
+                depth: 3,
+                fields: 7,
+                aliases: 2
+            }
+        );
+    }
+}
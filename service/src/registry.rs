@@ -0,0 +1,303 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use reqwest::Url;
+use serde_json::{json, Value};
+use thegraph::types::DeploymentId;
+use tokio::sync::Semaphore;
+
+use crate::error::SubgraphServiceError;
+
+const REGISTERED_DEPLOYMENT_QUERY: &str = r#"
+    query registeredDeployment($ipfsHash: String!) {
+        subgraphDeployments(where: { ipfsHash: $ipfsHash }, first: 1) {
+            id
+        }
+    }
+"#;
+
+/// Checks that deployments are registered in a subgraph registry, like the
+/// network subgraph, remembering the answers for a while.
+pub struct DeploymentRegistry {
+    url: Url,
+    client: reqwest::Client,
+    cache_ttl: Duration,
+    cache_max_entries: usize,
+    cache: RwLock<HashMap<DeploymentId, (bool, Instant)>>,
+    lookups: Semaphore,
+}
+
+impl DeploymentRegistry {
+    /// Answers are remembered for `cache_ttl`, and once `cache_max_entries`
+    /// are remembered, the oldest answer makes room for a new one. At most
+    /// `max_concurrent_lookups` deployments are looked up at a time.
+    pub fn new(
+        url: Url,
+        client: reqwest::Client,
+        cache_ttl: Duration,
+        cache_max_entries: usize,
+        max_concurrent_lookups: usize,
+    ) -> Self {
+        Self {
+            url,
+            client,
+            cache_ttl,
+            cache_max_entries,
+            cache: RwLock::new(HashMap::new()),
+            lookups: Semaphore::new(max_concurrent_lookups),
+        }
+    }
+
+    /// Fail with `DeploymentNotRegistered` unless the deployment is registered.
+    pub async fn check(&self, deployment: &DeploymentId) -> Result<(), SubgraphServiceError> {
+        if self.is_registered(deployment).await? {
+            Ok(())
+        } else {
+            Err(SubgraphServiceError::DeploymentNotRegistered(*deployment))
+        }
+    }
+
+    async fn is_registered(&self, deployment: &DeploymentId) -> Result<bool, SubgraphServiceError> {
+        if let Some(registered) = self.cached(deployment) {
+            return Ok(registered);
+        }
+
+        let _permit = self
+            .lookups
+            .acquire()
+            .await
+            .map_err(|e| SubgraphServiceError::DeploymentRegistryError(e.into()))?;
+        // The deployment may have been looked up while waiting
+        if let Some(registered) = self.cached(deployment) {
+            return Ok(registered);
+        }
+
+        let registered = self
+            .query(deployment)
+            .await
+            .map_err(SubgraphServiceError::DeploymentRegistryError)?;
+        self.remember(*deployment, registered);
+        Ok(registered)
+    }
+
+    fn cached(&self, deployment: &DeploymentId) -> Option<bool> {
+        match self.cache.read().unwrap().get(deployment) {
+            Some((registered, checked_at)) if checked_at.elapsed() < self.cache_ttl => {
+                Some(*registered)
+            }
+            _ => None,
+        }
+    }
+
+    fn remember(&self, deployment: DeploymentId, registered: bool) {
+        if self.cache_max_entries == 0 {
+            return;
+        }
+        // Deployment ids come from clients, so don't keep expired answers around
+        let mut cache = self.cache.write().unwrap();
+        cache.retain(|_, (_, checked_at)| checked_at.elapsed() < self.cache_ttl);
+        if cache.len() >= self.cache_max_entries && !cache.contains_key(&deployment) {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, (_, checked_at))| *checked_at)
+                .map(|(deployment, _)| *deployment);
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+        cache.insert(deployment, (registered, Instant::now()));
+    }
+
+    async fn query(&self, deployment: &DeploymentId) -> Result<bool, anyhow::Error> {
+        let response: Value = self
+            .client
+            .post(self.url.clone())
+            .json(&json!({
+                "query": REGISTERED_DEPLOYMENT_QUERY,
+                "variables": { "ipfsHash": deployment.to_string() },
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if let Some(errors) = response.get("errors") {
+            return Err(anyhow!("Registry query failed: {errors}"));
+        }
+        let deployments = response
+            .pointer("/data/subgraphDeployments")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("Registry response is missing `subgraphDeployments`"))?;
+        Ok(!deployments.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use wiremock::{
+        matchers::{body_string_contains, method},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    const REGISTERED: &str = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+    const UNREGISTERED: &str = "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB";
+
+    async fn mock_registry() -> MockServer {
+        let registry = MockServer::start().await;
+        registry
+            .register(
+                Mock::given(method("POST"))
+                    .and(body_string_contains(REGISTERED))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "data": { "subgraphDeployments": [{ "id": "0x01" }] }
+                    })))
+                    // Later checks are answered from the cache
+                    .expect(1),
+            )
+            .await;
+        registry
+            .register(
+                Mock::given(method("POST"))
+                    .and(body_string_contains(UNREGISTERED))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                        "data": { "subgraphDeployments": [] }
+                    }))),
+            )
+            .await;
+        registry
+    }
+
+    #[tokio::test]
+    async fn test_registered_deployment() {
+        let registry = mock_registry().await;
+        let registry = DeploymentRegistry::new(
+            registry.uri().parse().unwrap(),
+            reqwest::Client::new(),
+            Duration::from_secs(60),
+            10,
+            1,
+        );
+
+        let deployment = DeploymentId::from_str(REGISTERED).unwrap();
+        assert!(registry.check(&deployment).await.is_ok());
+        assert!(registry.check(&deployment).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_deployment() {
+        let registry = mock_registry().await;
+        let registry = DeploymentRegistry::new(
+            registry.uri().parse().unwrap(),
+            reqwest::Client::new(),
+            Duration::from_secs(60),
+            10,
+            1,
+        );
+
+        let deployment = DeploymentId::from_str(UNREGISTERED).unwrap();
+        assert!(matches!(
+            registry.check(&deployment).await,
+            Err(SubgraphServiceError::DeploymentNotRegistered(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_expired_answers_are_removed() {
+        let registry = MockServer::start().await;
+        registry
+            .register(Mock::given(method("POST")).respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({
+                    "data": { "subgraphDeployments": [] }
+                })),
+            ))
+            .await;
+        let registry = DeploymentRegistry::new(
+            registry.uri().parse().unwrap(),
+            reqwest::Client::new(),
+            Duration::from_millis(50),
+            10,
+            1,
+        );
+
+        let _ = registry
+            .check(&DeploymentId::from_str(REGISTERED).unwrap())
+            .await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let _ = registry
+            .check(&DeploymentId::from_str(UNREGISTERED).unwrap())
+            .await;
+
+        let cache = registry.cache.read().unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains_key(&DeploymentId::from_str(UNREGISTERED).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_oldest_answer_is_evicted() {
+        let registry = mock_registry().await;
+        let registry = DeploymentRegistry::new(
+            registry.uri().parse().unwrap(),
+            reqwest::Client::new(),
+            Duration::from_secs(60),
+            1,
+            1,
+        );
+
+        let _ = registry
+            .check(&DeploymentId::from_str(REGISTERED).unwrap())
+            .await;
+        let _ = registry
+            .check(&DeploymentId::from_str(UNREGISTERED).unwrap())
+            .await;
+
+        let cache = registry.cache.read().unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains_key(&DeploymentId::from_str(UNREGISTERED).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_waiting_lookups_use_the_cached_answer() {
+        let registry = MockServer::start().await;
+        registry
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_json(json!({
+                                "data": { "subgraphDeployments": [{ "id": "0x01" }] }
+                            }))
+                            .set_delay(Duration::from_millis(100)),
+                    )
+                    .expect(1),
+            )
+            .await;
+        let registry = DeploymentRegistry::new(
+            registry.uri().parse().unwrap(),
+            reqwest::Client::new(),
+            Duration::from_secs(60),
+            10,
+            1,
+        );
+
+        // Only one lookup runs at a time, the others are answered once it's done
+        let deployment = DeploymentId::from_str(REGISTERED).unwrap();
+        let (first, second, third) = tokio::join!(
+            registry.check(&deployment),
+            registry.check(&deployment),
+            registry.check(&deployment)
+        );
+        assert!(first.is_ok() && second.is_ok() && third.is_ok());
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    sync::{Arc, Mutex},
+};
+
+use axum::http::HeaderMap;
+use tokio::sync::oneshot;
+
+/// Header clients use to ask for their queries to be forwarded ahead of
+/// others while queries are queued.
+pub const PRIORITY_HEADER: &str = "x-priority";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    /// Priority requested in the priority header, either by name or as a
+    /// number clamped to 0 (low) to 2 (high). Defaults to normal.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let Some(value) = headers
+            .get(PRIORITY_HEADER)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Priority::Normal;
+        };
+        match value.trim().to_lowercase().as_str() {
+            "low" => Priority::Low,
+            "normal" => Priority::Normal,
+            "high" => Priority::High,
+            value => match value.parse::<i64>() {
+                Ok(level) if level <= 0 => Priority::Low,
+                Ok(1) => Priority::Normal,
+                Ok(_) => Priority::High,
+                Err(_) => Priority::Normal,
+            },
+        }
+    }
+}
+
+/// Limits how many queries are forwarded to graph-node at once. Queries
+/// beyond the limit wait in line, higher priorities first and in arrival
+/// order within a priority.
+pub struct RequestQueue {
+    state: Arc<Mutex<QueueState>>,
+}
+
+struct QueueState {
+    available: usize,
+    waiting: BinaryHeap<Waiter>,
+    arrivals: u64,
+}
+
+struct Waiter {
+    priority: Priority,
+    arrival: u64,
+    permit: oneshot::Sender<Permit>,
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.arrival.cmp(&self.arrival))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+/// Allows forwarding a query, until dropped.
+pub struct Permit {
+    state: Option<Arc<Mutex<QueueState>>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        let mut queue = state.lock().unwrap();
+        // Hand the permit to the next waiter that is still around
+        while let Some(waiter) = queue.waiting.pop() {
+            let permit = Permit {
+                state: Some(state.clone()),
+            };
+            match waiter.permit.send(permit) {
+                Ok(()) => return,
+                Err(mut permit) => {
+                    permit.state = None;
+                }
+            }
+        }
+        queue.available += 1;
+    }
+}
+
+impl RequestQueue {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(QueueState {
+                available: max_concurrent,
+                waiting: BinaryHeap::new(),
+                arrivals: 0,
+            })),
+        }
+    }
+
+    /// Wait until a query of the given priority may be forwarded.
+    pub async fn acquire(&self, priority: Priority) -> Permit {
+        let receiver = {
+            let mut queue = self.state.lock().unwrap();
+            if queue.available > 0 {
+                queue.available -= 1;
+                return Permit {
+                    state: Some(self.state.clone()),
+                };
+            }
+            let (sender, receiver) = oneshot::channel();
+            let arrival = queue.arrivals;
+            queue.arrivals += 1;
+            queue.waiting.push(Waiter {
+                priority,
+                arrival,
+                permit: sender,
+            });
+            receiver
+        };
+        // Senders are only dropped after sending a permit
+        receiver.await.expect("Request queue permit")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::HeaderValue;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    fn priority(value: &'static str) -> Priority {
+        let mut headers = HeaderMap::new();
+        headers.insert(PRIORITY_HEADER, HeaderValue::from_static(value));
+        Priority::from_headers(&headers)
+    }
+
+    #[test]
+    fn test_priority_header() {
+        assert_eq!(Priority::from_headers(&HeaderMap::new()), Priority::Normal);
+        assert_eq!(priority("high"), Priority::High);
+        assert_eq!(priority("Low"), Priority::Low);
+        assert_eq!(priority("-3"), Priority::Low);
+        assert_eq!(priority("1"), Priority::Normal);
+        assert_eq!(priority("10"), Priority::High);
+        assert_eq!(priority("urgent"), Priority::Normal);
+    }
+
+    #[tokio::test]
+    async fn test_higher_priorities_are_dequeued_first() {
+        let queue = Arc::new(RequestQueue::new(1));
+        let held = queue.acquire(Priority::Normal).await;
+
+        let (order_sender, mut order) = mpsc::unbounded_channel();
+        for priority in [Priority::Low, Priority::Normal, Priority::High] {
+            let queue = queue.clone();
+            let order_sender = order_sender.clone();
+            tokio::spawn(async move {
+                let _permit = queue.acquire(priority).await;
+                order_sender.send(priority).unwrap();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            });
+            // Make sure the waiters queue up in order
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(order_sender);
+        drop(held);
+
+        let mut dequeued = Vec::new();
+        while let Some(priority) = order.recv().await {
+            dequeued.push(priority);
+        }
+        assert_eq!(
+            dequeued,
+            vec![Priority::High, Priority::Normal, Priority::Low]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_waiters_are_skipped() {
+        let queue = RequestQueue::new(1);
+        let held = queue.acquire(Priority::Normal).await;
+
+        // Give up on waiting
+        let waiting =
+            tokio::time::timeout(Duration::from_millis(10), queue.acquire(Priority::High)).await;
+        assert!(waiting.is_err());
+
+        drop(held);
+        tokio::time::timeout(Duration::from_millis(10), queue.acquire(Priority::Low))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header::AUTHORIZATION, HeaderMap},
+    response::IntoResponse,
+    Json,
+};
+use indexer_config::{Config as MainConfig, ConfigPrefix, ServiceConfig};
+use serde_json::json;
+use subtle::ConstantTimeEq;
+use tracing::{info, warn};
+
+use crate::{error::SubgraphServiceError, service::SubgraphServiceState};
+
+/// Re-read the configuration file and use the new service configuration for
+/// subsequent requests. An invalid file is rejected and the current
+/// configuration kept.
+pub async fn reload_config(
+    State(state): State<Arc<SubgraphServiceState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, SubgraphServiceError> {
+    let current = state.service_config();
+    let authorized = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .zip(current.admin_token.as_deref())
+        // An empty token would let `Bearer ` through
+        .is_some_and(|(value, token)| {
+            !token.is_empty() && bool::from(value.as_bytes().ct_eq(token.as_bytes()))
+        });
+    if !authorized {
+        return Err(SubgraphServiceError::AdminUnauthorized);
+    }
+
+    let config = MainConfig::parse(ConfigPrefix::Service, &state.config_file).map_err(|e| {
+        warn!(file = ?state.config_file, error = %e, "Rejected configuration reload");
+        SubgraphServiceError::InvalidConfig(e)
+    })?;
+
+    let restart_required = startup_only_changes(&current, &config.service);
+    for field in &restart_required {
+        warn!(field = %format!("service.{field}"), "Ignoring changed setting until restart");
+    }
+
+    state.set_service_config(config.service);
+    info!(file = ?state.config_file, "Reloaded configuration");
+
+    Ok(Json(json!({
+        "reloaded": true,
+        "restartRequired": restart_required,
+    })))
+}
+
+/// Settings, by path below `service`, that differ between the configurations
+/// but only take effect on restart, because the server, routes, clients or
+/// caches were set up with them at startup.
+fn startup_only_changes(current: &ServiceConfig, new: &ServiceConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    macro_rules! check {
+        ($($name:literal => $($field:ident).+),* $(,)?) => {
+            $(
+                // Not all settings implement `PartialEq`
+                if format!("{:?}", current.$($field).+) != format!("{:?}", new.$($field).+) {
+                    changed.push($name);
+                }
+            )*
+        };
+    }
+    check!(
+        // Server and routes
+        "host_and_port" => host_and_port,
+        "url_prefix" => url_prefix,
+        "serve_network_subgraph" => serve_network_subgraph,
+        "serve_escrow_subgraph" => serve_escrow_subgraph,
+        "serve_auth_token" => serve_auth_token,
+        "free_query_auth_token" => free_query_auth_token,
+        "readiness_check_attestations" => readiness_check_attestations,
+        "strict_trailing_slash" => strict_trailing_slash,
+        "error_log_min_interval_secs" => error_log_min_interval_secs,
+        "global_request_timeout_secs" => global_request_timeout_secs,
+        "health_token" => health_token,
+        "enable_root_info" => enable_root_info,
+        "build_header" => build_header,
+        "byte_rate_limit_per_client" => byte_rate_limit_per_client,
+        "max_request_bytes" => max_request_bytes,
+        "shutdown_grace_secs" => shutdown_grace_secs,
+        "enable_cost_route" => enable_cost_route,
+        "tap" => tap,
+        "statsd_address" => statsd_address,
+        "trace_export_file" => trace_export_file,
+        "response_transforms" => response_transforms,
+        "attestation_overrides_file" => attestation_overrides_file,
+        "cost_models_dir" => cost_models_dir,
+        // Graph-node client, error cooldown and circuit breaker
+        "upstream.compress" => upstream.compress,
+        "upstream.http_proxy" => upstream.http_proxy,
+        "upstream.https_proxy" => upstream.https_proxy,
+        "upstream.no_proxy" => upstream.no_proxy,
+        "upstream.pool_idle_timeout_secs" => upstream.pool_idle_timeout_secs,
+        "upstream.pool_max_idle_per_host" => upstream.pool_max_idle_per_host,
+        "upstream.http2_prior_knowledge" => upstream.http2_prior_knowledge,
+        "upstream.error_cooldown_ms" => upstream.error_cooldown_ms,
+        "upstream.error_cooldown_threshold" => upstream.error_cooldown_threshold,
+        "upstream.error_cooldown_window_ms" => upstream.error_cooldown_window_ms,
+        "upstream.circuit_breaker_threshold" => upstream.circuit_breaker_threshold,
+        "upstream.circuit_breaker_cooldown_secs" => upstream.circuit_breaker_cooldown_secs,
+        "upstream.circuit_min_open_duration_ms" => upstream.circuit_min_open_duration_ms,
+        // Status cache and head tracker
+        "status.cache_ttl_secs" => status.cache_ttl_secs,
+        "status.cache_refresh_ahead_ms" => status.cache_refresh_ahead_ms,
+        "status.cache_max_entries" => status.cache_max_entries,
+        "status.max_concurrent_cache_refreshes" => status.max_concurrent_cache_refreshes,
+        "status.extra_root_fields" => status.extra_root_fields,
+        "status.block_poll_interval_secs" => status.block_poll_interval_secs,
+        // Query queue, persisted queries and deployment registry
+        "query.persisted_queries_max_entries" => query.persisted_queries_max_entries,
+        "query.max_concurrent_queries" => query.max_concurrent_queries,
+        "query.deployment_registry_url" => query.deployment_registry_url,
+        "query.deployment_registry_cache_secs" => query.deployment_registry_cache_secs,
+        "query.deployment_registry_cache_max_entries" => query.deployment_registry_cache_max_entries,
+        "query.deployment_registry_max_concurrent_lookups" => query.deployment_registry_max_concurrent_lookups,
+    );
+    // The token can be changed, but the route is only added at startup
+    if current.admin_token.is_some() != new.admin_token.is_some() {
+        changed.push("admin_token");
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing::post,
+        Router,
+    };
+    use tempfile::NamedTempFile;
+    use tower::ServiceExt;
+
+    use crate::test_utils;
+
+    use super::*;
+
+    const TOKEN: &str = "admin-token";
+
+    fn config_file(extra: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        let minimal = std::fs::read_to_string("../config/minimal-config-example.toml").unwrap();
+        write!(file, "{minimal}\n{extra}").unwrap();
+        file
+    }
+
+    async fn reload(state: Arc<SubgraphServiceState>, token: Option<&str>) -> StatusCode {
+        let app = Router::new()
+            .route("/admin/reload", post(reload_config))
+            .with_state(state);
+        let mut request = Request::post("/admin/reload");
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    async fn state(config_file: &NamedTempFile) -> Arc<SubgraphServiceState> {
+        let mut state = test_utils::subgraph_service_state("http://localhost:8000").await;
+        state.service_config_mut().admin_token = Some(TOKEN.to_string());
+        state.config_file = config_file.path().to_path_buf();
+        Arc::new(state)
+    }
+
+    #[tokio::test]
+    async fn test_reload_config() {
+        let file = config_file("[service.query]\nrequire_variables = true\n");
+        let state = state(&file).await;
+        assert!(!state.service_config().query.require_variables);
+
+        assert_eq!(reload(state.clone(), Some(TOKEN)).await, StatusCode::OK);
+        assert!(state.service_config().query.require_variables);
+    }
+
+    #[tokio::test]
+    async fn test_startup_only_changes_are_reported() {
+        let file = config_file(
+            "[service]\nhost_and_port = \"0.0.0.0:7700\"\nenable_cost_route = false\n\
+             [service.upstream]\ncircuit_breaker_threshold = 3\n\
+             [service.query]\nrequire_variables = true\n",
+        );
+        let state = state(&file).await;
+        let app = Router::new()
+            .route("/admin/reload", post(reload_config))
+            .with_state(state.clone());
+
+        let response = app
+            .oneshot(
+                Request::post("/admin/reload")
+                    .header(AUTHORIZATION, format!("Bearer {TOKEN}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["restartRequired"],
+            json!([
+                "host_and_port",
+                "enable_cost_route",
+                "upstream.circuit_breaker_threshold",
+                "admin_token",
+            ])
+        );
+        // Reloadable settings apply anyway
+        assert!(state.service_config().query.require_variables);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config_is_rejected() {
+        let file = config_file("[service.query\nrequire_variables = true\n");
+        let state = state(&file).await;
+
+        assert_eq!(
+            reload(state.clone(), Some(TOKEN)).await,
+            StatusCode::BAD_REQUEST
+        );
+        // The current configuration is kept
+        assert!(!state.service_config().query.require_variables);
+        assert_eq!(state.service_config().admin_token.as_deref(), Some(TOKEN));
+    }
+
+    #[tokio::test]
+    async fn test_reload_requires_admin_token() {
+        let file = config_file("[service.query]\nrequire_variables = true\n");
+        let state = state(&file).await;
+
+        assert_eq!(reload(state.clone(), None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            reload(state.clone(), Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert!(!state.service_config().query.require_variables);
+    }
+
+    #[tokio::test]
+    async fn test_empty_admin_token_is_rejected() {
+        let file = config_file("[service.query]\nrequire_variables = true\n");
+        let mut state = test_utils::subgraph_service_state("http://localhost:8000").await;
+        state.service_config_mut().admin_token = Some(String::new());
+        state.config_file = file.path().to_path_buf();
+        let state = Arc::new(state);
+
+        assert_eq!(
+            reload(state.clone(), Some("")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert!(!state.service_config().query.require_variables);
+    }
+}
@@ -1,19 +1,28 @@
 // Copyright 2023-, GraphOps and Semiotic Labs.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 
 use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
 use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
 use axum::extract::State;
+use graphql::graphql_parser::query as q;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thegraph::types::DeploymentId;
 
 use crate::database::{self, CostModel};
+use crate::error::SubgraphServiceError;
+use crate::query_validation;
 use crate::service::SubgraphServiceState;
 
+use super::status::selected_fields;
+
+/// Root fields of cost queries, besides introspection.
+const SUPPORTED_ROOT_FIELDS: &[&str] = &["costModel", "costModels"];
+
 #[derive(Clone, Debug, Serialize, Deserialize, SimpleObject)]
 pub struct GraphQlCostModel {
     pub deployment: String,
@@ -36,18 +45,54 @@ pub struct Query;
 
 #[Object]
 impl Query {
+    /// Cost models of the given deployments, in the same order. Unknown or
+    /// invalid deployments get `null` entries. Without deployments, all cost
+    /// models are returned.
     async fn cost_models(
         &self,
         ctx: &Context<'_>,
         deployments: Vec<String>,
-    ) -> Result<Vec<GraphQlCostModel>, anyhow::Error> {
+    ) -> Result<Vec<Option<GraphQlCostModel>>, anyhow::Error> {
+        let state = ctx.data_unchecked::<Arc<SubgraphServiceState>>();
+        if deployments.is_empty() {
+            return Ok(all_cost_models(state)
+                .await?
+                .into_iter()
+                .map(|model| Some(model.into()))
+                .collect());
+        }
+
         let deployment_ids = deployments
+            .iter()
+            .map(|s| DeploymentId::from_str(s).ok())
+            .collect::<Vec<_>>();
+        let known_ids = deployment_ids.iter().flatten().copied().collect::<Vec<_>>();
+        // An empty filter would return all cost models
+        let mut cost_models = if known_ids.is_empty() {
+            HashMap::new()
+        } else {
+            database::cost_models(&state.database, &known_ids)
+                .await?
+                .into_iter()
+                .map(|model| (model.deployment, model))
+                .collect::<HashMap<_, _>>()
+        };
+
+        // Cost model files take precedence over the database
+        let files = state.cost_model_files.read().unwrap();
+        for deployment in &known_ids {
+            if let Some(model) = files.get(deployment) {
+                cost_models.insert(*deployment, model.clone());
+            }
+        }
+        Ok(deployment_ids
             .into_iter()
-            .map(|s| DeploymentId::from_str(&s))
-            .collect::<Result<Vec<DeploymentId>, _>>()?;
-        let pool = &ctx.data_unchecked::<Arc<SubgraphServiceState>>().database;
-        let cost_models = database::cost_models(pool, &deployment_ids).await?;
-        Ok(cost_models.into_iter().map(|m| m.into()).collect())
+            .map(|deployment| {
+                deployment
+                    .and_then(|deployment| cost_models.get(&deployment).cloned())
+                    .map(GraphQlCostModel::from)
+            })
+            .collect())
     }
 
     async fn cost_model(
@@ -56,13 +101,31 @@ impl Query {
         deployment: String,
     ) -> Result<Option<GraphQlCostModel>, anyhow::Error> {
         let deployment_id = DeploymentId::from_str(&deployment)?;
-        let pool = &ctx.data_unchecked::<Arc<SubgraphServiceState>>().database;
-        database::cost_model(pool, &deployment_id)
+        let state = ctx.data_unchecked::<Arc<SubgraphServiceState>>();
+        let file_model = state
+            .cost_model_files
+            .read()
+            .unwrap()
+            .get(&deployment_id)
+            .cloned();
+        if let Some(model) = file_model {
+            return Ok(Some(model.into()));
+        }
+        database::cost_model(&state.database, &deployment_id)
             .await
             .map(|model_opt| model_opt.map(GraphQlCostModel::from))
     }
 }
 
+/// All cost models, with cost model files taking precedence over the database.
+async fn all_cost_models(state: &SubgraphServiceState) -> Result<Vec<CostModel>, anyhow::Error> {
+    let mut cost_models = database::cost_models(&state.database, &[]).await?;
+    let files = state.cost_model_files.read().unwrap();
+    cost_models.retain(|model| !files.contains_key(&model.deployment));
+    cost_models.extend(files.values().cloned());
+    Ok(cost_models)
+}
+
 pub type CostSchema = Schema<Query, EmptyMutation, EmptySubscription>;
 
 pub async fn build_schema() -> CostSchema {
@@ -72,10 +135,193 @@ pub async fn build_schema() -> CostSchema {
 pub async fn cost(
     State(state): State<Arc<SubgraphServiceState>>,
     req: GraphQLRequest,
-) -> GraphQLResponse {
-    state
+) -> Result<GraphQLResponse, SubgraphServiceError> {
+    let request = req.into_inner();
+    // Queries that don't parse are left for the schema to report
+    if let Ok(document) = query_validation::parse_query(&request.query) {
+        let unsupported_fields = unsupported_root_fields(&document);
+        if !unsupported_fields.is_empty() {
+            return Err(SubgraphServiceError::UnsupportedCostQueryFields(
+                unsupported_fields,
+            ));
+        }
+    }
+
+    Ok(state
         .cost_schema
-        .execute(req.into_inner().data(state.clone()))
+        .execute(request.data(state.clone()))
         .await
-        .into()
+        .into())
+}
+
+/// Root fields of a cost query other than the cost model and introspection
+/// fields.
+fn unsupported_root_fields(document: &q::Document<'_, String>) -> Vec<String> {
+    let mut unsupported = Vec::new();
+    for field in query_validation::root_selection_sets(document).flat_map(selected_fields) {
+        if !SUPPORTED_ROOT_FIELDS.contains(&field.name.as_str())
+            && !field.name.starts_with("__")
+            && !unsupported.contains(&field.name)
+        {
+            unsupported.push(field.name.clone());
+        }
+    }
+    unsupported
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, routing::post, Router};
+    use reqwest::StatusCode;
+    use serde_json::json;
+    use sqlx::PgPool;
+    use tower::ServiceExt;
+
+    use crate::test_utils;
+
+    use super::*;
+
+    fn unsupported(query: &str) -> Vec<String> {
+        unsupported_root_fields(&query_validation::parse_query(query).unwrap())
+    }
+
+    #[test]
+    fn test_unsupported_root_fields() {
+        assert!(unsupported("{ costModels(deployments: []) { model } }").is_empty());
+        assert!(unsupported("{ __typename __schema { types { name } } }").is_empty());
+        assert_eq!(
+            unsupported(
+                "{ costModel(deployment: \"Qm\") { model } indexingStatuses { subgraph } }"
+            ),
+            vec!["indexingStatuses"]
+        );
+        assert_eq!(
+            unsupported("mutation { setCostModel { model } }"),
+            vec!["setCostModel"]
+        );
+        assert_eq!(
+            unsupported("{ ... on Query { chains { network } } }"),
+            vec!["chains"]
+        );
+    }
+
+    const DEPLOYMENT: &str = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+    const OTHER_DEPLOYMENT: &str = "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB";
+
+    async fn cost_models(state: SubgraphServiceState, deployments: &[&str]) -> Value {
+        let state = Arc::new(state);
+        let query = format!(
+            "{{ costModels(deployments: {}) {{ deployment model }} }}",
+            json!(deployments)
+        );
+        let response = state
+            .cost_schema
+            .execute(async_graphql::Request::new(query).data(state.clone()))
+            .await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        response.data.into_json().unwrap()["costModels"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_invalid_deployments_are_null() {
+        let state = test_utils::subgraph_service_state("http://graph-node.invalid").await;
+        assert_eq!(
+            cost_models(state, &["invalid", "Qm"]).await,
+            json!([null, null])
+        );
+    }
+
+    #[sqlx::test]
+    async fn test_cost_models_preserve_order(pool: PgPool) {
+        sqlx::query(
+            r#"CREATE TABLE "CostModels"(
+                id INT,
+                deployment VARCHAR NOT NULL,
+                model TEXT,
+                variables JSONB,
+                PRIMARY KEY( deployment )
+            )"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query(r#"INSERT INTO "CostModels" (deployment, model) VALUES ($1, $2)"#)
+            .bind(format!(
+                "{:#x}",
+                DeploymentId::from_str(OTHER_DEPLOYMENT).unwrap()
+            ))
+            .bind("default => 0.00025;")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut state = test_utils::subgraph_service_state("http://graph-node.invalid").await;
+        state.database = pool;
+        let file_model = CostModel {
+            deployment: DeploymentId::from_str(DEPLOYMENT).unwrap(),
+            model: Some("default => 1;".to_string()),
+            variables: None,
+        };
+        state
+            .cost_model_files
+            .write()
+            .unwrap()
+            .insert(file_model.deployment, file_model);
+
+        let unknown = "QmCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC";
+        assert_eq!(
+            cost_models(
+                state,
+                &[
+                    OTHER_DEPLOYMENT,
+                    "invalid",
+                    unknown,
+                    DEPLOYMENT,
+                    OTHER_DEPLOYMENT
+                ]
+            )
+            .await,
+            json!([
+                { "deployment": OTHER_DEPLOYMENT, "model": "default => 0.00025;" },
+                null,
+                null,
+                { "deployment": DEPLOYMENT, "model": "default => 1;" },
+                { "deployment": OTHER_DEPLOYMENT, "model": "default => 0.00025;" },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_fields_are_rejected() {
+        let state = test_utils::subgraph_service_state("http://graph-node.invalid").await;
+        let app = Router::new()
+            .route("/cost", post(cost))
+            .with_state(Arc::new(state));
+
+        let response = app
+            .oneshot(
+                Request::post("/cost")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        json!({ "query": "{ indexingStatuses { subgraph } }" }).to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["errors"][0]["extensions"]["code"],
+            "UNSUPPORTED_COST_FIELDS"
+        );
+        assert_eq!(
+            body["errors"][0]["extensions"]["fields"],
+            json!(["indexingStatuses"])
+        );
+    }
 }
@@ -0,0 +1,68 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde_json::json;
+
+use crate::service::SubgraphServiceState;
+
+/// Liveness check: answers as long as the process serves requests.
+pub async fn health() -> impl IntoResponse {
+    Json(json!({ "healthy": true }))
+}
+
+/// Readiness check: fails while the most recent graph-node request within the
+/// readiness window failed.
+pub async fn ready(State(state): State<Arc<SubgraphServiceState>>) -> impl IntoResponse {
+    let window = Duration::from_secs(state.service_config().upstream.readiness_window_secs);
+    match state.upstream_health.recent_failure(window) {
+        None => (StatusCode::OK, Json(json!({ "ready": true }))),
+        Some(error) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({
+                "ready": false,
+                "unhealthy": { "graph-node": error },
+            })),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::test_utils;
+
+    use super::*;
+
+    async fn get_ready(state: Arc<SubgraphServiceState>) -> (StatusCode, Value) {
+        let app = Router::new().route("/ready", get(ready)).with_state(state);
+        let response = app
+            .oneshot(Request::get("/ready").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_ready_follows_upstream_health() {
+        let state = Arc::new(test_utils::subgraph_service_state("http://graph-node.invalid").await);
+        assert_eq!(get_ready(state.clone()).await.0, StatusCode::OK);
+
+        state.upstream_health.record_failure("connection refused");
+        let (status, body) = get_ready(state.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["unhealthy"]["graph-node"], "connection refused");
+
+        state.upstream_health.record_success();
+        assert_eq!(get_ready(state).await.0, StatusCode::OK);
+    }
+}
@@ -1,7 +1,12 @@
 // Copyright 2023-, GraphOps and Semiotic Labs.
 // SPDX-License-Identifier: Apache-2.0
 
+pub mod admin;
 pub mod cost;
+mod health;
 mod status;
 
-pub use status::status;
+pub use health::{health, ready};
+pub use status::{
+    deployments_status, status, status_head, supported_root_fields, versioned_status,
+};
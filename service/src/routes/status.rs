@@ -1,50 +1,139 @@
 // Copyright 2023-, GraphOps and Semiotic Labs.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use async_graphql_axum::GraphQLRequest;
-use axum::{extract::State, response::IntoResponse, Json};
+use anyhow::anyhow;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Json,
+};
 use graphql::graphql_parser::query as q;
+use indexer_config::ServiceQueryConfig;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Map, Value};
+use thegraph::types::DeploymentId;
 use thegraph_graphql_http::{
     http::request::{IntoRequestParameters, RequestParameters},
     http_client::{ReqwestExt, ResponseError},
 };
+use tokio::sync::OwnedSemaphorePermit;
+use tracing::{debug, warn};
+
+use crate::{
+    error::SubgraphServiceError, logging::loggable_query, metrics, query_validation,
+    server_timing::ServerTiming, service::SubgraphServiceState, status_cache::StatusCache,
+    transforms, upstream,
+};
 
-use crate::{error::SubgraphServiceError, service::SubgraphServiceState};
+/// Status root fields that are always supported.
+const BUILTIN_ROOT_FIELDS: &[&str] = &[
+    "indexingStatuses",
+    "chains",
+    "latestBlock",
+    "earliestBlock",
+    "publicProofsOfIndexing",
+    "entityChangesInBlock",
+    "blockData",
+    "cachedEthereumCalls",
+    "subgraphFeatures",
+    "apiVersions",
+];
+
+/// The built-in status root fields together with the configured extra ones.
+pub fn supported_root_fields(extra: &[String]) -> HashSet<String> {
+    BUILTIN_ROOT_FIELDS
+        .iter()
+        .map(ToString::to_string)
+        .chain(extra.iter().cloned())
+        .collect()
+}
 
 lazy_static::lazy_static! {
-    static ref SUPPORTED_ROOT_FIELDS: HashSet<&'static str> =
-        vec![
-            "indexingStatuses",
-            "chains",
-            "latestBlock",
-            "earliestBlock",
-            "publicProofsOfIndexing",
-            "entityChangesInBlock",
-            "blockData",
-            "cachedEthereumCalls",
-            "subgraphFeatures",
-            "apiVersions",
-        ].into_iter().collect();
-}
-
-struct WrappedGraphQLRequest(async_graphql::Request);
-
-impl IntoRequestParameters for WrappedGraphQLRequest {
+    /// Fields supported below some of the supported root fields, by dotted
+    /// path. Fields below other paths are left for graph-node to check.
+    static ref SUPPORTED_NESTED_FIELDS: HashMap<&'static str, HashSet<&'static str>> =
+        HashMap::from([
+            (
+                "indexingStatuses",
+                HashSet::from([
+                    "subgraph",
+                    "synced",
+                    "health",
+                    "entityCount",
+                    "fatalError",
+                    "nonFatalErrors",
+                    "chains",
+                    "node",
+                    "paused",
+                    "historyBlocks",
+                ]),
+            ),
+            (
+                "indexingStatuses.chains",
+                HashSet::from([
+                    "network",
+                    "chainHeadBlock",
+                    "earliestBlock",
+                    "latestBlock",
+                    "lastHealthyBlock",
+                ]),
+            ),
+            (
+                "publicProofsOfIndexing",
+                HashSet::from(["deployment", "block", "proofOfIndexing"]),
+            ),
+            (
+                "subgraphFeatures",
+                HashSet::from([
+                    "apiVersion",
+                    "specVersion",
+                    "features",
+                    "dataSources",
+                    "handlers",
+                    "network",
+                ]),
+            ),
+            ("apiVersions", HashSet::from(["version"])),
+        ]);
+}
+
+struct WrappedGraphQLRequest {
+    request: async_graphql::Request,
+    /// Variables exactly as sent by the client, forwarded instead of the parsed
+    /// ones so that they reach graph-node unchanged
+    raw_variables: Option<Map<String, Value>>,
+}
+
+impl WrappedGraphQLRequest {
+    fn new(request: async_graphql::Request, body: &[u8]) -> Self {
+        Self {
+            raw_variables: raw_variables(body),
+            request,
+        }
+    }
+}
+
+// Implemented on a reference so the request can be sent again when retrying
+impl IntoRequestParameters for &WrappedGraphQLRequest {
     fn into_request_parameters(self) -> RequestParameters {
         RequestParameters {
-            query: self.0.query.into(),
-            operation_name: self.0.operation_name,
-            variables: Map::from_iter(self.0.variables.iter().map(|(name, value)| {
-                (
-                    name.as_str().to_string(),
-                    value.clone().into_json().unwrap(),
-                )
-            })),
-            extensions: Map::from_iter(self.0.extensions.into_iter().map(|(name, value)| {
+            query: self.request.query.clone().into(),
+            operation_name: self.request.operation_name.clone(),
+            variables: self.raw_variables.clone().unwrap_or_else(|| {
+                Map::from_iter(self.request.variables.iter().map(|(name, value)| {
+                    (
+                        name.as_str().to_string(),
+                        value.clone().into_json().unwrap(),
+                    )
+                }))
+            }),
+            extensions: Map::from_iter(self.request.extensions.iter().map(|(name, value)| {
                 (
                     name.as_str().to_string(),
                     value.clone().into_json().unwrap(),
@@ -54,17 +143,325 @@ impl IntoRequestParameters for WrappedGraphQLRequest {
     }
 }
 
+/// Parse the status request body, falling back to the configured default query
+/// if the body is empty.
+fn parse_request(
+    body: &[u8],
+    default_query: Option<&str>,
+) -> Result<async_graphql::Request, SubgraphServiceError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return default_query
+            .map(async_graphql::Request::new)
+            .ok_or_else(|| {
+                SubgraphServiceError::InvalidStatusQuery(anyhow!("Empty request body"))
+            });
+    }
+
+    serde_json::from_slice(body).map_err(|e| SubgraphServiceError::InvalidStatusQuery(e.into()))
+}
+
+/// Extract the variables from the request body without going through
+/// `async_graphql`'s value representation.
+fn raw_variables(body: &[u8]) -> Option<Map<String, Value>> {
+    match serde_json::from_slice::<Value>(body)
+        .ok()?
+        .get_mut("variables")?
+        .take()
+    {
+        Value::Object(variables) => Some(variables),
+        _ => None,
+    }
+}
+
+/// The value of a string argument, passed either inline or as a variable.
+fn string_argument<'v>(
+    value: &'v q::Value<'_, String>,
+    variables: &'v async_graphql::Variables,
+) -> Option<&'v str> {
+    match value {
+        q::Value::String(value) => Some(value.as_str()),
+        q::Value::Variable(name) => match variables.get(&async_graphql::Name::new(name)) {
+            Some(async_graphql::Value::String(value)) => Some(value.as_str()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Reject queries with a `network` or `chain` argument naming a disabled
+/// chain.
+fn reject_disabled_chains(
+    document: &q::Document<'_, String>,
+    variables: &async_graphql::Variables,
+    disabled_chains: &[String],
+) -> Result<(), SubgraphServiceError> {
+    let mut disabled = None;
+    for selection_set in query_validation::root_selection_sets(document) {
+        query_validation::visit_fields(selection_set, &mut |field| {
+            for (name, value) in &field.arguments {
+                if name != "network" && name != "chain" {
+                    continue;
+                }
+                if let Some(chain) = string_argument(value, variables) {
+                    if disabled_chains.iter().any(|disabled| disabled == chain) {
+                        disabled.get_or_insert_with(|| chain.to_string());
+                    }
+                }
+            }
+        });
+    }
+    match disabled {
+        Some(chain) => Err(SubgraphServiceError::DisabledChain(chain)),
+        None => Ok(()),
+    }
+}
+
+/// Make sure `blockData` fields come with the `network` and `blockHash`
+/// arguments graph-node requires, and that the hash is a valid block hash.
+fn validate_block_data(
+    document: &q::Document<'_, String>,
+    variables: &async_graphql::Variables,
+) -> Result<(), SubgraphServiceError> {
+    for selection_set in query_validation::root_selection_sets(document) {
+        for item in &selection_set.items {
+            let q::Selection::Field(field) = item else {
+                continue;
+            };
+            if field.name != "blockData" {
+                continue;
+            }
+
+            let argument = |name: &str| {
+                field
+                    .arguments
+                    .iter()
+                    .find(|(argument, _)| argument == name)
+                    .map(|(_, value)| value)
+            };
+            if argument("network").is_none() {
+                return Err(SubgraphServiceError::InvalidBlockDataQuery(
+                    "missing `network` argument".to_string(),
+                ));
+            }
+            let Some(block_hash) = argument("blockHash") else {
+                return Err(SubgraphServiceError::InvalidBlockDataQuery(
+                    "missing `blockHash` argument".to_string(),
+                ));
+            };
+            if !string_argument(block_hash, variables).is_some_and(is_block_hash) {
+                return Err(SubgraphServiceError::InvalidBlockDataQuery(
+                    "`blockHash` must be a 32 byte hex string".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_block_hash(hash: &str) -> bool {
+    let hash = hash.strip_prefix("0x").unwrap_or(hash);
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Header asking `/status` to only validate the query instead of running it.
+pub const VALIDATE_ONLY_HEADER: &str = "x-validate-only";
+
+/// Header asking `/status` to report subgraph ids as they are, without
+/// replacing them by their `subgraph_id_aliases`.
+pub const DISABLE_ID_REWRITE_HEADER: &str = "x-disable-id-rewrite";
+
+fn header_flag(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true") || value == "1")
+}
+
+#[derive(Deserialize)]
+pub struct StatusParams {
+    /// Comma-separated `indexingStatuses` fields to return, or `*` for all
+    fields: Option<String>,
+    /// Report which graph-node served the request in `extensions.backend`, if
+    /// enabled by `debug_reports_backend`
+    #[serde(default)]
+    debug: bool,
+}
+
+/// Drop all but the given fields from each `indexingStatuses` result.
+fn prune_indexing_statuses(data: &mut Value, fields: &[&str]) {
+    let Some(statuses) = data
+        .get_mut("indexingStatuses")
+        .and_then(Value::as_array_mut)
+    else {
+        return;
+    };
+    for status in statuses.iter_mut().filter_map(Value::as_object_mut) {
+        status.retain(|field, _| fields.contains(&field.as_str()));
+    }
+}
+
+/// Fields of a selection set, including those of inline fragments.
+pub(super) fn selected_fields<'a, 'd>(
+    selection_set: &'d q::SelectionSet<'a, String>,
+) -> Vec<&'d q::Field<'a, String>> {
+    let mut fields = Vec::new();
+    for item in &selection_set.items {
+        match item {
+            q::Selection::Field(field) => fields.push(field),
+            q::Selection::InlineFragment(fragment) => {
+                fields.extend(selected_fields(&fragment.selection_set))
+            }
+            q::Selection::FragmentSpread(_) => {}
+        }
+    }
+    fields
+}
+
+/// Collect the dotted paths of fields below `path` that aren't supported.
+fn unsupported_nested_fields(
+    path: &str,
+    selection_set: &q::SelectionSet<'_, String>,
+    unsupported: &mut Vec<String>,
+) {
+    let supported = SUPPORTED_NESTED_FIELDS.get(path);
+    for field in selected_fields(selection_set) {
+        let field_path = format!("{path}.{}", field.name);
+        if field.name != "__typename"
+            && supported.is_some_and(|supported| !supported.contains(field.name.as_str()))
+        {
+            unsupported.push(field_path);
+            continue;
+        }
+        unsupported_nested_fields(&field_path, &field.selection_set, unsupported);
+    }
+}
+
+/// Warnings about the deprecated fields selected below `field`, by dotted path.
+fn deprecated_fields<'c>(
+    path: String,
+    field: &q::Field<'_, String>,
+    deprecated: &'c HashMap<String, String>,
+    warnings: &mut BTreeMap<String, &'c String>,
+) {
+    for child in selected_fields(&field.selection_set) {
+        deprecated_fields(
+            format!("{path}.{}", child.name),
+            child,
+            deprecated,
+            warnings,
+        );
+    }
+    if let Some(hint) = deprecated.get(&path) {
+        warnings.insert(path, hint);
+    }
+}
+
+/// Warnings for `extensions.warnings` about deprecated fields the query selects.
+fn deprecation_warnings(
+    root_selections: &[&q::Field<'_, String>],
+    deprecated: &HashMap<String, String>,
+) -> Vec<Value> {
+    if deprecated.is_empty() {
+        return Vec::new();
+    }
+    let mut warnings = BTreeMap::new();
+    for field in root_selections {
+        deprecated_fields(field.name.clone(), field, deprecated, &mut warnings);
+    }
+    warnings
+        .into_iter()
+        .map(|(field, hint)| {
+            json!({
+                "message": format!("Field `{field}` is deprecated: {hint}"),
+                "field": field,
+            })
+        })
+        .collect()
+}
+
+/// Replace `subgraph` values anywhere in the response by their alias. Each
+/// value is looked up once as a whole, so aliases don't chain and overlapping
+/// keys can't interfere with each other.
+fn replace_subgraph_ids(value: &mut Value, aliases: &HashMap<String, String>) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match value {
+                    Value::String(id) if key == "subgraph" => {
+                        if let Some(alias) = aliases.get(id.as_str()) {
+                            *id = alias.clone();
+                        }
+                    }
+                    value => replace_subgraph_ids(value, aliases),
+                }
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                replace_subgraph_ids(value, aliases);
+            }
+        }
+        _ => {}
+    }
+}
+
 // Custom middleware function to process the request before reaching the main handler
 pub async fn status(
     State(state): State<Arc<SubgraphServiceState>>,
-    request: GraphQLRequest,
+    Query(params): Query<StatusParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<impl IntoResponse, SubgraphServiceError> {
+    serve_status(state, params, &headers, body, None).await
+}
+
+/// Serve a status query against one of the configured status API versions,
+/// which only allow their own root fields.
+pub async fn versioned_status(
+    State(state): State<Arc<SubgraphServiceState>>,
+    Path(version): Path<String>,
+    Query(params): Query<StatusParams>,
+    headers: HeaderMap,
+    body: Bytes,
 ) -> Result<impl IntoResponse, SubgraphServiceError> {
-    let request = request.into_inner();
+    let Some(fields) = state
+        .service_config()
+        .status
+        .versions
+        .get(&version)
+        .cloned()
+    else {
+        return Err(SubgraphServiceError::UnknownStatusVersion(version));
+    };
+    serve_status(state, params, &headers, body, Some(&fields)).await
+}
+
+/// Forward a status query to graph-node, allowing only `supported_fields` as
+/// root fields, or the default ones if not given. With the
+/// [`VALIDATE_ONLY_HEADER`], the query is only validated and never forwarded.
+async fn serve_status(
+    state: Arc<SubgraphServiceState>,
+    params: StatusParams,
+    headers: &HeaderMap,
+    body: Bytes,
+    supported_fields: Option<&[String]>,
+) -> Result<(HeaderMap, Json<Value>), SubgraphServiceError> {
+    let mut timing = ServerTiming::start();
+    let service_config = state.service_config();
+    let request = parse_request(&body, service_config.status.default_query.as_deref())?;
+
+    debug!(
+        query = %loggable_query(
+            &request.query,
+            service_config.log_query_collapse_whitespace
+        ),
+        "Handling status query"
+    );
 
     let query: q::Document<String> = q::parse_query(request.query.as_str())
         .map_err(|e| SubgraphServiceError::InvalidStatusQuery(e.into()))?;
 
-    let root_fields = query
+    let root_selections: Vec<_> = query
         .definitions
         .iter()
         // This gives us all root selection sets
@@ -76,42 +473,1644 @@ pub async fn status(
             },
             q::Definition::Fragment(fragment) => Some(&fragment.selection_set),
         })
-        // This gives us all field names of root selection sets (and potentially non-root fragments)
-        .flat_map(|selection_set| {
-            selection_set
-                .items
-                .iter()
-                .filter_map(|item| match item {
-                    q::Selection::Field(field) => Some(&field.name),
-                    _ => None,
-                })
-                .collect::<HashSet<_>>()
+        // This gives us all fields of root selection sets (and potentially non-root fragments)
+        .flat_map(selected_fields)
+        .collect();
+
+    let root_fields: Vec<_> = root_selections
+        .iter()
+        .map(|field| &field.name)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    // Version queries are about this service, not graph-node
+    if !root_fields.is_empty() && root_fields.iter().all(|field| *field == "version") {
+        let version = json!({
+            "version": state.release.version(),
+            "commit": state.release.commit(),
         });
+        return Ok((
+            HeaderMap::new(),
+            Json(json!({ "data": { "version": version } })),
+        ));
+    }
 
-    let unsupported_root_fields: Vec<_> = root_fields
-        .filter(|field| !SUPPORTED_ROOT_FIELDS.contains(field.as_str()))
+    let mut unsupported_fields: Vec<_> = root_fields
+        .iter()
+        .filter(|field| match supported_fields {
+            Some(supported_fields) => !supported_fields.contains(field),
+            None => !state.status_root_fields.contains(field.as_str()),
+        })
         .map(ToString::to_string)
         .collect();
+    for field in &root_selections {
+        unsupported_nested_fields(&field.name, &field.selection_set, &mut unsupported_fields);
+    }
 
-    if !unsupported_root_fields.is_empty() {
+    if !unsupported_fields.is_empty() {
         return Err(SubgraphServiceError::UnsupportedStatusQueryFields(
-            unsupported_root_fields,
+            unsupported_fields,
         ));
     }
 
-    let result = state
-        .graph_node_client
-        .post(&state.graph_node_status_url)
-        .send_graphql::<Value>(WrappedGraphQLRequest(request))
+    if let Some(allowed) = &service_config.status.allowed_variables {
+        let disallowed: Vec<_> = request
+            .variables
+            .keys()
+            .filter(|name| !allowed.iter().any(|allowed| allowed == name.as_str()))
+            .map(ToString::to_string)
+            .collect();
+        if !disallowed.is_empty() {
+            return Err(SubgraphServiceError::DisallowedStatusVariables(disallowed));
+        }
+    }
+    if service_config.status.validate_block_data {
+        validate_block_data(&query, &request.variables)?;
+    }
+    reject_disabled_chains(
+        &query,
+        &request.variables,
+        &service_config.status.disabled_chains,
+    )?;
+    if header_flag(headers, VALIDATE_ONLY_HEADER) {
+        return Ok((HeaderMap::new(), Json(json!({ "data": { "valid": true } }))));
+    }
+
+    let request = WrappedGraphQLRequest::new(request, &body);
+    let timeout = upstream::status_timeout(
+        &service_config.upstream,
+        root_fields.iter().map(|field| field.as_str()),
+    );
+    let cache_key = StatusCache::key(
+        &query,
+        request.request.operation_name.as_deref(),
+        &request.request.variables,
+    );
+    timing.record("parse");
+
+    // Block heads are kept up to date by the head tracker, if enabled
+    let head = state
+        .head_tracker
+        .as_ref()
+        .and_then(|tracker| tracker.serve(&query));
+    let result = match (head, state.status_cache.get(&cache_key)) {
+        (Some(head), _) => Ok(head),
+        (None, Some(data)) => {
+            if let Some(permit) = state.status_cache.claim_refresh(&cache_key) {
+                refresh_status(
+                    state.clone(),
+                    body.clone(),
+                    cache_key.clone(),
+                    timeout,
+                    permit,
+                );
+            }
+            Ok(data)
+        }
+        (None, None) => {
+            // Concurrent misses on the same query wait for a single fetch
+            let _fetching = state.status_cache.lock_fetch(&cache_key).await;
+            match state.status_cache.get(&cache_key) {
+                Some(data) => Ok(data),
+                None => fetch_status(&state, &request, timeout, cache_key.clone()).await?,
+            }
+        }
+    };
+    timing.record("upstream");
+
+    let fields: Option<Vec<&str>> = match params.fields.as_deref() {
+        Some("*") => None,
+        Some(fields) => Some(fields.split(',').map(str::trim).collect()),
+        None => service_config
+            .status
+            .indexing_statuses_default_fields
+            .as_ref()
+            .map(|fields| fields.iter().map(String::as_str).collect()),
+    };
+
+    let mut response = match result {
+        Ok(mut data) => {
+            if let Some(fields) = &fields {
+                prune_indexing_statuses(&mut data, fields);
+            }
+            if !header_flag(headers, DISABLE_ID_REWRITE_HEADER) {
+                replace_subgraph_ids(&mut data, &service_config.status.subgraph_id_aliases);
+            }
+            json!({ "data": data })
+        }
+        Err(ResponseError::Failure { errors }) => json!({ "errors": errors }),
+        Err(ResponseError::Empty) => return Err(SubgraphServiceError::EmptyStatusResponse),
+    };
+    if service_config.sanitize_upstream_errors {
+        transforms::sanitize_errors(&mut response);
+    }
+    state.response_transforms.apply(&mut response);
+    if !service_config.status.log_status_diffs_only
+        || state.status_log.changed(&cache_key, &response)
+    {
+        debug!(
+            query = %loggable_query(
+                &request.request.query,
+                service_config.log_query_collapse_whitespace
+            ),
+            %response,
+            "Status response"
+        );
+    }
+    // The backend URL is internal, only report it where allowed
+    if params.debug && service_config.status.debug_reports_backend {
+        transforms::insert_extension(&mut response, "backend", json!(state.graph_node_status_url));
+    }
+    if service_config.echo_query_hash {
+        transforms::insert_extension(
+            &mut response,
+            "queryHash",
+            json!(query_validation::query_hash(&request.request.query)),
+        );
+    }
+    // Deprecated fields are still served, clients are only warned about them
+    let warnings = deprecation_warnings(&root_selections, &service_config.status.deprecated_fields);
+    if !warnings.is_empty() {
+        transforms::insert_extension(&mut response, "warnings", json!(warnings));
+    }
+    timing.record("rewrite");
+
+    let mut headers = HeaderMap::new();
+    if service_config.server_timing_header {
+        timing.insert_into(&mut headers);
+    }
+    if let Some(cache_control) = &service_config.status.cache_control_header {
+        headers.insert(header::CACHE_CONTROL, cache_control.clone());
+    }
+    Ok((headers, Json(response)))
+}
+
+/// Send a status query to graph-node and cache its result if successful.
+async fn fetch_status(
+    state: &SubgraphServiceState,
+    request: &WrappedGraphQLRequest,
+    timeout: Duration,
+    cache_key: String,
+) -> Result<Result<Value, ResponseError>, SubgraphServiceError> {
+    let service_config = state.service_config();
+    let start = Instant::now();
+    let result = send_status_query::<Value>(
+        state,
+        request,
+        timeout,
+        service_config.upstream.status_max_retries,
+    )
+    .await;
+
+    if let Some(slo_ms) = service_config.status.slo_ms {
+        let elapsed = start.elapsed();
+        if elapsed > Duration::from_millis(slo_ms) {
+            warn!(?elapsed, slo_ms, "Status query exceeded the upstream SLO");
+            metrics::STATUS_SLO_VIOLATIONS.inc();
+        }
+    }
+
+    let result = result?;
+    // Errors are not cached, so the next request tries again
+    if let Ok(data) = &result {
+        state.status_cache.insert(cache_key, data.clone());
+    }
+    Ok(result)
+}
+
+/// Send a status query to graph-node, unless the circuit breaker is open, and
+/// record the outcome for the circuit breaker and readiness check.
+async fn send_status_query<T: DeserializeOwned>(
+    state: &SubgraphServiceState,
+    request: &WrappedGraphQLRequest,
+    timeout: Duration,
+    max_retries: u32,
+) -> Result<Result<T, ResponseError>, SubgraphServiceError> {
+    if !state.circuit_breaker.allow_request() {
+        return Err(SubgraphServiceError::UpstreamUnavailable);
+    }
+    let start = Instant::now();
+    let result = upstream::with_retries(max_retries, || {
+        state
+            .graph_node_client
+            .post(&state.graph_node_status_url)
+            .timeout(timeout)
+            .send_graphql::<T>(request)
+    })
+    .await;
+    metrics::record_upstream_latency("status", start.elapsed());
+
+    let result = result.map_err(|e| {
+        metrics::record_upstream_error("status", metrics::upstream_error_kind(&e));
+        state.circuit_breaker.record_failure();
+        state.upstream_health.record_failure(&e);
+        SubgraphServiceError::StatusQueryError(e.into())
+    })?;
+    metrics::record_upstream_success();
+    state.circuit_breaker.record_success();
+    state.upstream_health.record_success();
+    Ok(result)
+}
+
+/// Refresh a cached status result that is about to expire in the background,
+/// while the cached result keeps being served.
+fn refresh_status(
+    state: Arc<SubgraphServiceState>,
+    body: Bytes,
+    cache_key: String,
+    timeout: Duration,
+    permit: OwnedSemaphorePermit,
+) {
+    tokio::spawn(async move {
+        let _permit = permit;
+        // The body was parsed successfully before
+        let Ok(request) = parse_request(
+            &body,
+            state.service_config().status.default_query.as_deref(),
+        ) else {
+            return;
+        };
+        let request = WrappedGraphQLRequest::new(request, &body);
+        match fetch_status(&state, &request, timeout, cache_key).await {
+            Ok(Ok(_)) => debug!("Refreshed cached status result"),
+            Ok(Err(e)) => warn!(error = ?e, "Failed to refresh cached status result"),
+            Err(e) => warn!(error = %e, "Failed to refresh cached status result"),
+        }
+    });
+}
+
+/// Answer `HEAD /status` for monitoring tools by checking that graph-node's
+/// status API responds, without returning a body.
+pub async fn status_head(
+    State(state): State<Arc<SubgraphServiceState>>,
+) -> Result<impl IntoResponse, SubgraphServiceError> {
+    let request = WrappedGraphQLRequest {
+        request: async_graphql::Request::new("{ __typename }"),
+        raw_variables: None,
+    };
+    let timeout = upstream::status_timeout(&state.service_config().upstream, []);
+    send_status_query::<Value>(&state, &request, timeout, 0)
+        .await?
+        .map_err(|e| SubgraphServiceError::StatusQueryError(anyhow!("{:?}", e)))?;
+
+    Ok([(header::CONTENT_TYPE, "application/json")])
+}
+
+const DEPLOYMENTS_STATUS_QUERY: &str = r#"{
+    indexingStatuses {
+        subgraph
+        synced
+        health
+        chains {
+            network
+            latestBlock { number hash }
+        }
+    }
+}"#;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexingStatuses {
+    indexing_statuses: Vec<IndexingStatus>,
+}
+
+#[derive(Deserialize)]
+struct IndexingStatus {
+    subgraph: String,
+    synced: bool,
+    health: String,
+    chains: Vec<ChainIndexingStatus>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChainIndexingStatus {
+    network: String,
+    latest_block: Option<BlockPointer>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct BlockPointer {
+    number: String,
+    hash: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeploymentStatusSummary {
+    deployment: String,
+    synced: bool,
+    health: String,
+    network: Option<String>,
+    latest_block: Option<BlockPointer>,
+}
+
+impl From<IndexingStatus> for DeploymentStatusSummary {
+    fn from(status: IndexingStatus) -> Self {
+        let chain = status.chains.into_iter().next();
+        Self {
+            deployment: status.subgraph,
+            synced: status.synced,
+            health: status.health,
+            network: chain.as_ref().map(|chain| chain.network.clone()),
+            latest_block: chain.and_then(|chain| chain.latest_block),
+        }
+    }
+}
+
+/// Whether `subgraph` may be queried according to the allowed and denied
+/// deployments, compared as `Qm…` ids.
+fn deployment_allowed(query_config: &ServiceQueryConfig, subgraph: &str) -> bool {
+    let listed = |deployments: &[DeploymentId]| {
+        deployments
+            .iter()
+            .any(|deployment| deployment.to_string() == subgraph)
+    };
+    !listed(&query_config.denied_deployments)
+        && (query_config.allowed_deployments.is_empty()
+            || listed(&query_config.allowed_deployments))
+}
+
+/// Summarize the indexing status of all deployments on the graph-node with a
+/// single upstream `indexingStatuses` query. Deployments that can't be queried
+/// are left out, and the others are listed by their alias, if any.
+pub async fn deployments_status(
+    State(state): State<Arc<SubgraphServiceState>>,
+) -> Result<impl IntoResponse, SubgraphServiceError> {
+    let request = WrappedGraphQLRequest {
+        request: async_graphql::Request::new(DEPLOYMENTS_STATUS_QUERY),
+        raw_variables: None,
+    };
+    let service_config = state.service_config();
+    let statuses = send_status_query::<IndexingStatuses>(
+        &state,
+        &request,
+        upstream::status_timeout(&service_config.upstream, []),
+        service_config.upstream.status_max_retries,
+    )
+    .await?
+    .map_err(|e| SubgraphServiceError::StatusQueryError(anyhow!("{:?}", e)))?;
+
+    let deployments = statuses
+        .indexing_statuses
+        .into_iter()
+        .filter(|status| deployment_allowed(&service_config.query, &status.subgraph))
+        .map(|mut status| {
+            if let Some(alias) = service_config
+                .status
+                .subgraph_id_aliases
+                .get(&status.subgraph)
+            {
+                status.subgraph = alias.clone();
+            }
+            DeploymentStatusSummary::from(status)
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(json!({ "deployments": deployments })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use axum::{
+        body::Body,
+        http::{HeaderValue, Request, StatusCode},
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
+    use wiremock::{
+        matchers::{body_partial_json, body_string_contains, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{head_tracker::HeadTracker, server_timing::SERVER_TIMING_HEADER, test_utils};
+
+    use super::*;
+
+    const DEFAULT_QUERY: &str = "{ indexingStatuses { subgraph health } }";
+    const CLIENT_QUERY: &str = "{ chains { network } }";
+
+    async fn mock_graph_node(expected_query: &str) -> MockServer {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path("/graphql"))
+                    .and(body_partial_json(json!({ "query": expected_query })))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} })))
+                    .expect(1),
+            )
+            .await;
+        graph_node
+    }
+
+    async fn send_status_query(graph_node: &MockServer, body: Body) -> StatusCode {
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().status.default_query = Some(DEFAULT_QUERY.to_string());
+
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+        app.oneshot(Request::post("/status").body(body).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_empty_body_runs_default_query() {
+        let graph_node = mock_graph_node(DEFAULT_QUERY).await;
+        assert_eq!(
+            send_status_query(&graph_node, Body::empty()).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_provided_query_is_used() {
+        let graph_node = mock_graph_node(CLIENT_QUERY).await;
+        let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+        assert_eq!(send_status_query(&graph_node, body).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_query_hash_is_echoed() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} }))),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().echo_query_hash = true;
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let mut hashes = Vec::new();
+        for query in [CLIENT_QUERY, "query {\n  chains {\n    network\n  }\n}"] {
+            let body = Body::from(json!({ "query": query }).to_string());
+            let response = app
+                .clone()
+                .oneshot(Request::post("/status").body(body).unwrap())
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            hashes.push(body["extensions"]["queryHash"].clone());
+        }
+        assert_eq!(hashes[0], json!(query_validation::query_hash(CLIENT_QUERY)));
+        assert_eq!(hashes[0], hashes[1]);
+    }
+
+    #[tokio::test]
+    async fn test_extra_root_fields() {
+        const NEW_FIELD_QUERY: &str = "{ newStatusField { value } }";
+
+        let graph_node = mock_graph_node(NEW_FIELD_QUERY).await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let app = |state: SubgraphServiceState| {
+            Router::new()
+                .route("/status", post(status))
+                .with_state(Arc::new(state))
+        };
+        let request = || {
+            Request::post("/status")
+                .body(Body::from(json!({ "query": NEW_FIELD_QUERY }).to_string()))
+                .unwrap()
+        };
+
+        let response = app(test_utils::subgraph_service_state(&graph_node.uri()).await)
+            .oneshot(request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        state.status_root_fields = supported_root_fields(&["newStatusField".to_string()]);
+        assert!(state.status_root_fields.contains("indexingStatuses"));
+        let response = app(state).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_fields_are_warned_about() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(
+                        json!({ "data": { "chains": [{ "network": "mainnet" }] } }),
+                    )),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().status.deprecated_fields = HashMap::from([(
+            "chains.network".to_string(),
+            "use `chains.id` instead".to_string(),
+        )]);
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let send = |query: &str| {
+            let request = Request::post("/status")
+                .body(Body::from(json!({ "query": query }).to_string()))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Value>(&body).unwrap()
+            }
+        };
+
+        let body = send(CLIENT_QUERY).await;
+        assert_eq!(body["data"]["chains"][0]["network"], "mainnet");
+        assert_eq!(
+            body["extensions"]["warnings"],
+            json!([{
+                "message": "Field `chains.network` is deprecated: use `chains.id` instead",
+                "field": "chains.network",
+            }])
+        );
+
+        let body = send("{ chains { __typename } }").await;
+        assert!(body.get("extensions").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disable_id_rewrite_header() {
+        const DEPLOYMENT: &str = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(Mock::given(method("POST")).respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({
+                    "data": { "indexingStatuses": [{ "subgraph": DEPLOYMENT }] }
+                })),
+            ))
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().status.subgraph_id_aliases =
+            HashMap::from([(DEPLOYMENT.to_string(), "my-subgraph".to_string())]);
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let subgraph = |disable_id_rewrite: Option<&'static str>| {
+            let mut request = Request::post("/status");
+            if let Some(value) = disable_id_rewrite {
+                request = request.header(DISABLE_ID_REWRITE_HEADER, value);
+            }
+            let body = json!({ "query": "{ indexingStatuses { subgraph } }" }).to_string();
+            let request = request.body(Body::from(body)).unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: Value = serde_json::from_slice(&body).unwrap();
+                body["data"]["indexingStatuses"][0]["subgraph"].clone()
+            }
+        };
+
+        assert_eq!(subgraph(None).await, "my-subgraph");
+        assert_eq!(subgraph(Some("false")).await, "my-subgraph");
+        assert_eq!(subgraph(Some("true")).await, DEPLOYMENT);
+    }
+
+    #[tokio::test]
+    async fn test_validate_only() {
+        // Nothing may reach graph-node
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(500))
+                    .expect(0),
+            )
+            .await;
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let send = |query: &str| {
+            let request = Request::post("/status")
+                .header(VALIDATE_ONLY_HEADER, "true")
+                .body(Body::from(json!({ "query": query }).to_string()))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, serde_json::from_slice::<Value>(&body).unwrap())
+            }
+        };
+
+        let (status, body) = send(CLIENT_QUERY).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "data": { "valid": true } }));
+
+        let (status, body) = send("{ chains { network } secrets { key } }").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["errors"][0]["extensions"]["fields"],
+            json!(["secrets"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_status_query_violates_slo() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST")).respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(json!({ "data": {} }))
+                        .set_delay(Duration::from_millis(100)),
+                ),
+            )
+            .await;
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().status.slo_ms = Some(10);
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let violations = metrics::STATUS_SLO_VIOLATIONS.get();
+        let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+        app.oneshot(Request::post("/status").body(body).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(metrics::STATUS_SLO_VIOLATIONS.get(), violations + 1);
+    }
+
+    #[tokio::test]
+    async fn test_big_int_variables_are_preserved() {
+        const BIG_INT: &str = "123456789012345678901234567890";
+
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(body_string_contains(BIG_INT))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} })))
+                    .expect(1),
+            )
+            .await;
+
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let body = format!(
+            r#"{{ "query": "query($block: Int) {{ entityChangesInBlock(blockNumber: $block) {{ subgraphId }} }}", "variables": {{ "block": {BIG_INT} }} }}"#
+        );
+        let response = app
+            .oneshot(Request::post("/status").body(Body::from(body)).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_deployments_status_summary() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(Mock::given(method("POST")).respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({
+                    "data": {
+                        "indexingStatuses": [
+                            {
+                                "subgraph": "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                                "synced": true,
+                                "health": "healthy",
+                                "chains": [{
+                                    "network": "mainnet",
+                                    "latestBlock": { "number": "100", "hash": "0x01" }
+                                }]
+                            },
+                            {
+                                "subgraph": "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB",
+                                "synced": false,
+                                "health": "failed",
+                                "chains": [{ "network": "gnosis", "latestBlock": null }]
+                            }
+                        ]
+                    }
+                })),
+            ))
+            .await;
+
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let app = Router::new()
+            .route("/status/deployments", get(deployments_status))
+            .with_state(Arc::new(state));
+
+        let response = app
+            .oneshot(
+                Request::get("/status/deployments")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "deployments": [
+                    {
+                        "deployment": "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                        "synced": true,
+                        "health": "healthy",
+                        "network": "mainnet",
+                        "latestBlock": { "number": "100", "hash": "0x01" }
+                    },
+                    {
+                        "deployment": "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB",
+                        "synced": false,
+                        "health": "failed",
+                        "network": "gnosis",
+                        "latestBlock": null
+                    }
+                ]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deployments_status_applies_filters_and_aliases() {
+        const DEPLOYMENT: &str = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+        const OTHER_DEPLOYMENT: &str = "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB";
+        const UNLISTED_DEPLOYMENT: &str = "QmCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC";
+
+        let graph_node = MockServer::start().await;
+        let statuses = [DEPLOYMENT, OTHER_DEPLOYMENT, UNLISTED_DEPLOYMENT].map(|subgraph| {
+            json!({
+                "subgraph": subgraph,
+                "synced": true,
+                "health": "healthy",
+                "chains": [{ "network": "mainnet", "latestBlock": null }]
+            })
+        });
+        graph_node
+            .register(
+                Mock::given(method("POST")).respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(json!({ "data": { "indexingStatuses": statuses } })),
+                ),
+            )
+            .await;
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let config = state.service_config_mut();
+        config.query.allowed_deployments = vec![
+            DeploymentId::from_str(DEPLOYMENT).unwrap(),
+            DeploymentId::from_str(OTHER_DEPLOYMENT).unwrap(),
+        ];
+        config.query.denied_deployments = vec![DeploymentId::from_str(OTHER_DEPLOYMENT).unwrap()];
+        config.status.subgraph_id_aliases =
+            HashMap::from([(DEPLOYMENT.to_string(), "my-subgraph".to_string())]);
+        let app = Router::new()
+            .route("/status/deployments", get(deployments_status))
+            .with_state(Arc::new(state));
+
+        let response = app
+            .oneshot(
+                Request::get("/status/deployments")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        let deployments: Vec<_> = body["deployments"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|deployment| deployment["deployment"].clone())
+            .collect();
+        assert_eq!(deployments, vec![json!("my-subgraph")]);
+    }
+
+    #[tokio::test]
+    async fn test_status_routes_use_circuit_breaker() {
+        // Nothing listens on port 1
+        let mut state = test_utils::subgraph_service_state("http://127.0.0.1:1").await;
+        state
+            .service_config_mut()
+            .upstream
+            .circuit_breaker_threshold = Some(1);
+        state.circuit_breaker = upstream::CircuitBreaker::new(&state.service_config().upstream);
+        let state = Arc::new(state);
+        let app = Router::new()
+            .route("/status", post(status).head(status_head))
+            .route("/status/deployments", get(deployments_status))
+            .with_state(state.clone());
+
+        let response = app
+            .clone()
+            .oneshot(Request::head("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(state
+            .upstream_health
+            .recent_failure(Duration::from_secs(60))
+            .is_some());
+
+        // Fails without trying to reach graph-node
+        let response = app
+            .oneshot(
+                Request::get("/status/deployments")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    const BLOCK_HASH: &str = "0x8f4c6e2d5b1a3e7f9c0d2b4a6e8f1c3d5b7a9e0f2c4d6b8a1e3f5c7d9b0a2e4f";
+
+    fn check_block_data(query: &str, variables: Value) -> Result<(), SubgraphServiceError> {
+        let document = q::parse_query::<String>(query).unwrap();
+        let variables = async_graphql::Variables::from_json(variables);
+        validate_block_data(&document, &variables)
+    }
+
+    #[test]
+    fn test_block_data_with_block_hash() {
+        assert!(check_block_data(
+            &format!(r#"{{ blockData(network: "mainnet", blockHash: "{BLOCK_HASH}") }}"#),
+            json!({})
+        )
+        .is_ok());
+        assert!(check_block_data(
+            r#"query($hash: String!) { blockData(network: "mainnet", blockHash: $hash) }"#,
+            json!({ "hash": BLOCK_HASH })
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_block_data_without_valid_block_hash() {
+        for (query, variables) in [
+            (r#"{ blockData(network: "mainnet") }"#, json!({})),
+            (r#"{ blockData(blockHash: "0x01") }"#, json!({})),
+            (
+                r#"{ blockData(network: "mainnet", blockHash: "0x01") }"#,
+                json!({}),
+            ),
+            (
+                r#"query($hash: String!) { blockData(network: "mainnet", blockHash: $hash) }"#,
+                json!({}),
+            ),
+        ] {
+            assert!(matches!(
+                check_block_data(query, variables),
+                Err(SubgraphServiceError::InvalidBlockDataQuery(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_enabled_and_disabled_chains() {
+        let disabled = vec!["goerli".to_string()];
+        let check = |query: &str, variables: Value| {
+            let document = q::parse_query::<String>(query).unwrap();
+            let variables = async_graphql::Variables::from_json(variables);
+            reject_disabled_chains(&document, &variables, &disabled)
+        };
+
+        let query = r#"query($network: String!) { blockHashFromNumber(network: $network, blockNumber: 1) }"#;
+        assert!(check(query, json!({ "network": "mainnet" })).is_ok());
+        assert!(matches!(
+            check(query, json!({ "network": "goerli" })),
+            Err(SubgraphServiceError::DisabledChain(chain)) if chain == "goerli"
+        ));
+        assert!(matches!(
+            check(
+                &format!(r#"{{ blockData(network: "goerli", blockHash: "{BLOCK_HASH}") }}"#),
+                json!({})
+            ),
+            Err(SubgraphServiceError::DisabledChain(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_block_data_without_block_hash_is_not_forwarded() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(200))
+                    .expect(0),
+            )
+            .await;
+
+        let body =
+            Body::from(json!({ "query": r#"{ blockData(network: "mainnet") }"# }).to_string());
+        assert_eq!(
+            send_status_query(&graph_node, body).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    async fn indexing_statuses(uri: &str) -> Value {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(Mock::given(method("POST")).respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({
+                    "data": {
+                        "indexingStatuses": [{
+                            "subgraph": "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                            "health": "healthy",
+                            "chains": [{ "network": "mainnet" }]
+                        }]
+                    }
+                })),
+            ))
+            .await;
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state
+            .service_config_mut()
+            .status
+            .indexing_statuses_default_fields =
+            Some(vec!["subgraph".to_string(), "health".to_string()]);
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let body =
+            json!({ "query": "{ indexingStatuses { subgraph health chains { network } } }" });
+        let response = app
+            .oneshot(
+                Request::post(uri)
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        body["data"]["indexingStatuses"][0].clone()
+    }
+
+    #[tokio::test]
+    async fn test_indexing_statuses_default_projection() {
+        assert_eq!(
+            indexing_statuses("/status").await,
+            json!({
+                "subgraph": "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                "health": "healthy"
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_indexing_statuses_projection_override() {
+        assert_eq!(
+            indexing_statuses("/status?fields=subgraph,chains").await,
+            json!({
+                "subgraph": "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                "chains": [{ "network": "mainnet" }]
+            })
+        );
+        assert_eq!(
+            indexing_statuses("/status?fields=*").await["chains"],
+            json!([{ "network": "mainnet" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_head_status() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path("/graphql"))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_json(json!({ "data": { "__typename": "Query" } })),
+                    ),
+            )
+            .await;
+
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let app = Router::new()
+            .route("/status", post(status).head(status_head))
+            .with_state(Arc::new(state));
+
+        let response = app
+            .oneshot(Request::head("/status").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_status_server_timing_header() {
+        let graph_node = mock_graph_node(CLIENT_QUERY).await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().server_timing_header = true;
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+        let response = app
+            .oneshot(Request::post("/status").body(body).unwrap())
+            .await
+            .unwrap();
+        let timing = response.headers()[SERVER_TIMING_HEADER].to_str().unwrap();
+        let phases = timing
+            .split(", ")
+            .map(|phase| phase.split(';').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(phases, vec!["parse", "upstream", "rewrite"]);
+    }
+
+    #[tokio::test]
+    async fn test_debug_reports_backend() {
+        let graph_node = mock_graph_node(CLIENT_QUERY).await;
+        let send = |state: SubgraphServiceState| async move {
+            let app = Router::new()
+                .route("/status", post(status))
+                .with_state(Arc::new(state));
+            let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+            let response = app
+                .oneshot(Request::post("/status?debug=true").body(body).unwrap())
+                .await
+                .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<Value>(&body).unwrap()
+        };
+
+        // Not reported unless enabled
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        assert!(send(state).await.get("extensions").is_none());
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().status.debug_reports_backend = true;
+        state.service_config_mut().echo_query_hash = true;
+        let body = send(state).await;
+        assert_eq!(
+            body["extensions"]["backend"],
+            format!("{}/graphql", graph_node.uri())
+        );
+        // Other extensions are kept
+        assert!(body["extensions"]["queryHash"].is_string());
+    }
+
+    #[test]
+    fn test_replace_subgraph_ids() {
+        let aliases = HashMap::from([
+            (
+                "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_string(),
+                "geo".to_string(),
+            ),
+            // Would apply to the result of the first alias if aliases chained
+            (
+                "geo".to_string(),
+                "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB".to_string(),
+            ),
+        ]);
+        let mut data = json!({
+            "indexingStatuses": [
+                { "subgraph": "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA", "health": "healthy" },
+                { "subgraph": "QmCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC", "health": "failed" }
+            ],
+            "network": "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+        });
+        replace_subgraph_ids(&mut data, &aliases);
+        assert_eq!(
+            data,
+            json!({
+                "indexingStatuses": [
+                    { "subgraph": "geo", "health": "healthy" },
+                    { "subgraph": "QmCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC", "health": "failed" }
+                ],
+                // Only `subgraph` values are rewritten
+                "network": "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_version_query_is_answered_locally() {
+        // Any request reaching graph-node fails the test
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(200))
+                    .expect(0),
+            )
+            .await;
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        for query in [
+            "{ version { version } }",
+            "query {\n  version {\n    version\n    commit\n  }\n}",
+        ] {
+            let body = Body::from(json!({ "query": query }).to_string());
+            let response = app
+                .clone()
+                .oneshot(Request::post("/status").body(body).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(
+                body["data"]["version"]["version"],
+                env!("CARGO_PKG_VERSION"),
+                "{query}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_versions_enforce_their_fields() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} }))),
+            )
+            .await;
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().status.versions = HashMap::from([
+            ("v1".to_string(), vec!["indexingStatuses".to_string()]),
+            (
+                "v2".to_string(),
+                vec!["indexingStatuses".to_string(), "chains".to_string()],
+            ),
+        ]);
+        let app = Router::new()
+            .route("/status/:version", post(versioned_status))
+            .with_state(Arc::new(state));
+
+        let send = |uri: &'static str, query: &'static str| {
+            let body = Body::from(json!({ "query": query }).to_string());
+            let app = app.clone();
+            async move {
+                app.oneshot(Request::post(uri).body(body).unwrap())
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        assert_eq!(send("/status/v1", DEFAULT_QUERY).await, StatusCode::OK);
+        assert_eq!(
+            send("/status/v1", CLIENT_QUERY).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(send("/status/v2", CLIENT_QUERY).await, StatusCode::OK);
+        // `latestBlock` is supported by `/status`, but not by any version
+        assert_eq!(
+            send("/status/v2", "{ latestBlock { number } }").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            send("/status/v3", DEFAULT_QUERY).await,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_results_are_cached() {
+        let graph_node = mock_graph_node(CLIENT_QUERY).await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.status_cache = StatusCache::new(Duration::from_secs(60), Duration::ZERO, 10, 1);
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        // The mock expects a single request, the second one is a cache hit
+        for query in [CLIENT_QUERY, "query {\n  chains {\n    network\n  }\n}"] {
+            let body = Body::from(json!({ "query": query }).to_string());
+            let response = app
+                .clone()
+                .oneshot(Request::post("/status").body(body).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_errors_are_not_cached() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_json(json!({ "errors": [{ "message": "not yet" }] })),
+                    )
+                    .expect(2),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.status_cache = StatusCache::new(Duration::from_secs(60), Duration::ZERO, 10, 1);
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        for _ in 0..2 {
+            let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+            app.clone()
+                .oneshot(Request::post("/status").body(body).unwrap())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_cache_misses_share_one_fetch() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_json(json!({ "data": { "chains": [] } }))
+                            .set_delay(Duration::from_millis(200)),
+                    )
+                    .expect(1),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.status_cache = StatusCache::new(Duration::from_millis(200), Duration::ZERO, 10, 1);
+        let cache_key = StatusCache::key(
+            &q::parse_query::<String>(CLIENT_QUERY).unwrap(),
+            None,
+            &Default::default(),
+        );
+        state.status_cache.insert(cache_key.clone(), json!({}));
+        tokio::time::sleep(Duration::from_millis(210)).await;
+        assert_eq!(state.status_cache.get(&cache_key), None);
+
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+        let requests: Vec<_> = (0..5)
+            .map(|_| {
+                let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+                tokio::spawn(
+                    app.clone()
+                        .oneshot(Request::post("/status").body(body).unwrap()),
+                )
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(request.await.unwrap().unwrap().status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_near_expiry_status_results_are_refreshed() {
+        let graph_node = MockServer::start().await;
+        for network in ["mainnet", "gnosis"] {
+            graph_node
+                .register(
+                    Mock::given(method("POST"))
+                        .respond_with(ResponseTemplate::new(200).set_body_json(
+                            json!({ "data": { "chains": [{ "network": network }] } }),
+                        ))
+                        .up_to_n_times(1)
+                        .expect(1),
+                )
+                .await;
+        }
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.status_cache = StatusCache::new(
+            Duration::from_millis(500),
+            Duration::from_millis(400),
+            10,
+            1,
+        );
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+        let network = || {
+            let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::post("/status").body(body).unwrap())
+                    .await
+                    .unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: Value = serde_json::from_slice(&body).unwrap();
+                body["data"]["chains"][0]["network"].clone()
+            }
+        };
+
+        assert_eq!(network().await, "mainnet");
+
+        // Near expiry, the cached result is served while it's refreshed
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(network().await, "mainnet");
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while network().await != "gnosis" {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
         .await
-        .map_err(|e| SubgraphServiceError::StatusQueryError(e.into()))?;
-
-    result
-        .map(|data| Json(json!({"data": data})))
-        .or_else(|e| match e {
-            ResponseError::Failure { errors } => Ok(Json(json!({
-                "errors": errors,
-            }))),
-            ResponseError::Empty => todo!(),
+        .expect("refreshed status result should be served");
+    }
+
+    #[tokio::test]
+    async fn test_block_heads_are_served_from_head_tracker() {
+        let graph_node = MockServer::start().await;
+        for number in ["100", "101"] {
+            graph_node
+                .register(
+                    Mock::given(method("POST"))
+                        .and(body_string_contains("earliestBlock"))
+                        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                            "data": {
+                                "earliestBlock": { "number": "1", "hash": "0x01" },
+                                "latestBlock": { "number": number, "hash": "0x02" },
+                            }
+                        })))
+                        .up_to_n_times(1),
+                )
+                .await;
+        }
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.head_tracker = Some(HeadTracker::spawn(
+            reqwest::Client::new(),
+            graph_node.uri(),
+            Duration::from_millis(200),
+        ));
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+        let latest_block = || {
+            let body = Body::from(json!({ "query": "{ latestBlock { number } }" }).to_string());
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::post("/status").body(body).unwrap())
+                    .await
+                    .unwrap();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let body: Value = serde_json::from_slice(&body).unwrap();
+                body["data"]["latestBlock"]["number"].clone()
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while latest_block().await != "100" {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
         })
+        .await
+        .expect("polled block head should be served");
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while latest_block().await != "101" {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("block head should be refreshed on the poll interval");
+
+        // Only the head tracker reached graph-node
+        for request in graph_node.received_requests().await.unwrap() {
+            assert!(String::from_utf8_lossy(&request.body).contains("earliestBlock"));
+        }
+    }
+
+    fn unsupported_fields(query: &str) -> Vec<String> {
+        let document = q::parse_query::<String>(query).unwrap();
+        let mut unsupported = Vec::new();
+        for definition in &document.definitions {
+            if let q::Definition::Operation(q::OperationDefinition::SelectionSet(selection_set)) =
+                definition
+            {
+                for field in selected_fields(selection_set) {
+                    unsupported_nested_fields(&field.name, &field.selection_set, &mut unsupported);
+                }
+            }
+        }
+        unsupported
+    }
+
+    #[test]
+    fn test_nested_status_fields() {
+        assert!(unsupported_fields(
+            "{ indexingStatuses { __typename subgraph chains { network latestBlock { number hash } } } }"
+        )
+        .is_empty());
+        assert_eq!(
+            unsupported_fields(
+                "{ indexingStatuses { subgraph secret chains { network foo } } apiVersions { bar } }"
+            ),
+            vec![
+                "indexingStatuses.secret",
+                "indexingStatuses.chains.foo",
+                "apiVersions.bar"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inline_fragments_are_checked() {
+        assert_eq!(
+            unsupported_fields(
+                "{ ... on Query { indexingStatuses { ... on SubgraphIndexingStatus { foo } } } }"
+            ),
+            vec!["indexingStatuses.foo"]
+        );
+    }
+
+    async fn send_with_variables(graph_node: &MockServer, variables: Value) -> StatusCode {
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().status.allowed_variables = Some(vec!["subgraphs".to_string()]);
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let query = "query q($subgraphs: [String!]) { indexingStatuses(subgraphs: $subgraphs) { subgraph } }";
+        let body = Body::from(json!({ "query": query, "variables": variables }).to_string());
+        app.oneshot(Request::post("/status").body(body).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_allowed_status_variables() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} })))
+                    .expect(1),
+            )
+            .await;
+        assert_eq!(
+            send_with_variables(&graph_node, json!({ "subgraphs": ["QmA"] })).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_status_variables() {
+        // Nothing may be forwarded
+        let graph_node = MockServer::start().await;
+        assert_eq!(
+            send_with_variables(
+                &graph_node,
+                json!({ "subgraphs": ["QmA"], "network": "mainnet" })
+            )
+            .await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_nested_status_field_is_rejected() {
+        let graph_node = MockServer::start().await;
+        let body = Body::from(
+            json!({ "query": "{ indexingStatuses { subgraph chains { foo } } }" }).to_string(),
+        );
+        assert_eq!(
+            send_status_query(&graph_node, body).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_empty_upstream_response_is_bad_gateway() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({}))),
+            )
+            .await;
+        let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+        assert_eq!(
+            send_status_query(&graph_node, body).await,
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_cache_control_header() {
+        let graph_node = mock_graph_node(CLIENT_QUERY).await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().status.cache_control = Some("max-age=5".to_string());
+        state.service_config_mut().status.cache_control_header =
+            Some(HeaderValue::from_static("max-age=5"));
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let body = Body::from(json!({ "query": CLIENT_QUERY }).to_string());
+        let response = app
+            .oneshot(Request::post("/status").body(body).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "max-age=5");
+    }
+
+    #[tokio::test]
+    async fn test_slow_status_field_times_out() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST")).respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(json!({ "data": {} }))
+                        .set_delay(Duration::from_millis(1500)),
+                ),
+            )
+            .await;
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().upstream.status_max_retries = 0;
+        state
+            .service_config_mut()
+            .upstream
+            .status_field_timeouts
+            .insert("publicProofsOfIndexing".to_string(), 1);
+        let app = Router::new()
+            .route("/status", post(status))
+            .with_state(Arc::new(state));
+
+        let send = |query: &str| {
+            let body = Body::from(json!({ "query": query }).to_string());
+            app.clone()
+                .oneshot(Request::post("/status").body(body).unwrap())
+        };
+        let slow = send("{ publicProofsOfIndexing(requests: []) { proofOfIndexing } }")
+            .await
+            .unwrap();
+        assert_eq!(slow.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // Other fields keep the default timeout
+        let other = send(CLIENT_QUERY).await.unwrap();
+        assert_eq!(other.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_empty_body_without_default_query() {
+        assert!(parse_request(b"", None).is_err());
+    }
 }
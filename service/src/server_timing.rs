@@ -0,0 +1,70 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::{Duration, Instant};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+pub const SERVER_TIMING_HEADER: HeaderName = HeaderName::from_static("server-timing");
+
+/// Durations of the phases of handling a request, reported to clients in a
+/// `Server-Timing` header.
+pub struct ServerTiming {
+    phases: Vec<(&'static str, Duration)>,
+    phase_start: Instant,
+}
+
+impl ServerTiming {
+    pub fn start() -> Self {
+        Self {
+            phases: Vec::new(),
+            phase_start: Instant::now(),
+        }
+    }
+
+    /// End the current phase and start the next one.
+    pub fn record(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.phases.push((phase, now - self.phase_start));
+        self.phase_start = now;
+    }
+
+    pub fn header_value(&self) -> HeaderValue {
+        let value = self
+            .phases
+            .iter()
+            .map(|(phase, duration)| format!("{phase};dur={:.3}", duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        HeaderValue::from_str(&value).expect("Server-Timing header is ASCII")
+    }
+
+    pub fn insert_into(&self, headers: &mut HeaderMap) {
+        headers.insert(SERVER_TIMING_HEADER, self.header_value());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_timing_header() {
+        let mut timing = ServerTiming::start();
+        timing.record("parse");
+        std::thread::sleep(Duration::from_millis(5));
+        timing.record("upstream");
+
+        let value = timing.header_value();
+        let phases = value.to_str().unwrap().split(", ").collect::<Vec<_>>();
+        assert_eq!(phases.len(), 2);
+        assert!(phases[0].starts_with("parse;dur="));
+
+        let upstream: f64 = phases[1]
+            .strip_prefix("upstream;dur=")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(upstream >= 5.0);
+    }
+}
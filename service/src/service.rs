@@ -1,41 +1,89 @@
 // Copyright 2023-, GraphOps and Semiotic Labs.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::sync::Arc;
-use std::time::Duration;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
 use super::{config::Config, error::SubgraphServiceError, routes};
 use anyhow::anyhow;
-use axum::{async_trait, routing::post, Json, Router};
-use indexer_common::indexer_service::http::{IndexerServiceImpl, IndexerServiceResponse};
-use indexer_config::Config as MainConfig;
-use reqwest::Url;
+use axum::{
+    async_trait,
+    http::{header, HeaderMap, HeaderValue},
+    middleware,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use indexer_common::indexer_service::http::{
+    require_health_token, IndexerServiceImpl, IndexerServiceResponse, UpstreamStatus,
+};
+use indexer_config::{Config as MainConfig, ServiceConfig};
+use reqwest::{StatusCode, Url};
 use serde_json::{json, Value};
 use sqlx::PgPool;
 use thegraph::types::{Attestation, DeploymentId};
 
-use crate::{cli::Cli, database};
+use crate::{
+    attestation_overrides::{self, AttestationOverrides},
+    cli::Cli,
+    cost_model_files::{self, CostModelFiles},
+    database,
+    features::{Feature, Features},
+    head_tracker::HeadTracker,
+    logging::{self, loggable_query},
+    metrics,
+    persisted_queries::PersistedQueries,
+    query_cost, query_validation,
+    registry::DeploymentRegistry,
+    request_queue::{Priority, RequestQueue},
+    server_timing::ServerTiming,
+    statsd,
+    status_cache::StatusCache,
+    transforms::{self, ResponseTransforms},
+    upstream,
+};
 
 use clap::Parser;
 use indexer_common::indexer_service::http::{
     IndexerService, IndexerServiceOptions, IndexerServiceRelease,
 };
-use tracing::error;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+const DEPLOYMENT_ID_HEADER: &str = "x-deployment-id";
+const UPSTREAM_LATENCY_HEADER: &str = "x-upstream-latency-ms";
+/// Longest part of an unsuccessful graph-node response body passed on to
+/// clients.
+const MAX_UPSTREAM_ERROR_LENGTH: usize = 512;
 
 #[derive(Debug)]
 struct SubgraphServiceResponse {
     inner: String,
     attestable: bool,
+    headers: HeaderMap,
+    upstream_status: StatusCode,
 }
 
 impl SubgraphServiceResponse {
-    pub fn new(inner: String, attestable: bool) -> Self {
-        Self { inner, attestable }
+    pub fn new(
+        inner: String,
+        attestable: bool,
+        headers: HeaderMap,
+        upstream_status: StatusCode,
+    ) -> Self {
+        Self {
+            inner,
+            attestable,
+            headers,
+            upstream_status,
+        }
     }
 }
 
 impl IndexerServiceResponse for SubgraphServiceResponse {
-    type Data = Json<Value>;
+    type Data = (HeaderMap, Extension<UpstreamStatus>, Json<Value>);
     type Error = SubgraphServiceError; // not used
 
     fn is_attestable(&self) -> bool {
@@ -47,22 +95,60 @@ impl IndexerServiceResponse for SubgraphServiceResponse {
     }
 
     fn finalize(self, attestation: Option<Attestation>) -> Self::Data {
-        Json(json!({
-            "graphQLResponse": self.inner,
-            "attestation": attestation
-        }))
+        (
+            self.headers,
+            Extension(UpstreamStatus(self.upstream_status.as_u16())),
+            Json(json!({
+                "graphQLResponse": self.inner,
+                "attestation": attestation
+            })),
+        )
     }
 }
 
 pub struct SubgraphServiceState {
     pub config: Config,
+    pub service_config: RwLock<Arc<ServiceConfig>>,
+    /// Configuration file the service was started with, re-read on reloads.
+    pub config_file: PathBuf,
     pub database: PgPool,
     pub cost_schema: routes::cost::CostSchema,
+    pub cost_model_files: CostModelFiles,
+    pub cost_models: query_cost::CostModelCache,
     pub graph_node_client: reqwest::Client,
+    pub error_cooldown: upstream::ErrorCooldown,
+    pub circuit_breaker: upstream::CircuitBreaker,
+    pub upstream_health: upstream::UpstreamHealth,
+    pub status_log: logging::ResponseDiffLog,
+    pub status_cache: StatusCache,
+    pub status_root_fields: HashSet<String>,
+    pub persisted_queries: PersistedQueries,
+    pub request_queue: Option<RequestQueue>,
+    pub attestation_overrides: AttestationOverrides,
+    pub response_transforms: ResponseTransforms,
+    pub deployment_registry: Option<DeploymentRegistry>,
+    pub head_tracker: Option<HeadTracker>,
+    pub release: IndexerServiceRelease,
     pub graph_node_status_url: String,
     pub graph_node_query_base_url: String,
 }
 
+impl SubgraphServiceState {
+    /// Current service configuration, which may be replaced by reloads.
+    pub fn service_config(&self) -> Arc<ServiceConfig> {
+        self.service_config.read().unwrap().clone()
+    }
+
+    pub fn set_service_config(&self, service_config: ServiceConfig) {
+        *self.service_config.write().unwrap() = Arc::new(service_config);
+    }
+
+    #[cfg(test)]
+    pub fn service_config_mut(&mut self) -> &mut ServiceConfig {
+        Arc::make_mut(self.service_config.get_mut().unwrap())
+    }
+}
+
 struct SubgraphService {
     state: Arc<SubgraphServiceState>,
 }
@@ -83,39 +169,342 @@ impl IndexerServiceImpl for SubgraphService {
     async fn process_request(
         &self,
         deployment: DeploymentId,
-        request: Self::Request,
+        mut request: Self::Request,
+        headers: HeaderMap,
     ) -> Result<(Self::Request, Self::Response), Self::Error> {
+        let mut timing = ServerTiming::start();
+        // The configuration may be reloaded meanwhile, stick to one version
+        let service_config = self.state.service_config();
+        let query_config = &service_config.query;
+        if query_config.denied_deployments.contains(&deployment)
+            || !(query_config.allowed_deployments.is_empty()
+                || query_config.allowed_deployments.contains(&deployment))
+        {
+            return Err(SubgraphServiceError::DeploymentNotAllowed(deployment));
+        }
+        if service_config
+            .query
+            .deprecated_deployments
+            .contains(&deployment)
+        {
+            return Err(SubgraphServiceError::DeploymentGone(deployment));
+        }
+        if let Some(registry) = &self.state.deployment_registry {
+            registry.check(&deployment).await?;
+        }
+
+        let features = Features::from_headers(&headers)?;
+
+        if service_config.query.validate_request_schema
+            || features.enabled(Feature::ValidateRequestSchema)
+        {
+            query_validation::validate_request_schema(&request)?;
+        }
+        if service_config.query.reject_unknown_extensions {
+            query_validation::reject_unknown_extensions(&request)?;
+        }
+        self.state.persisted_queries.resolve(&mut request)?;
+        if service_config.query.require_query {
+            query_validation::require_query(&request)?;
+        }
+
+        // Refuse queries that cost more than the client is willing to pay
+        if let Some(max_cost) = query_cost::max_query_cost(&headers)? {
+            let file_model = self
+                .state
+                .cost_model_files
+                .read()
+                .unwrap()
+                .get(&deployment)
+                .cloned();
+            let model = match file_model {
+                Some(model) => Some(model),
+                None => database::cost_model(&self.state.database, &deployment)
+                    .await
+                    .map_err(SubgraphServiceError::InvalidCostModel)?,
+            };
+            if let Some(model) = model {
+                query_cost::check_query_cost(
+                    &self.state.cost_models,
+                    &model,
+                    &request,
+                    &service_config.query.operation_cost_multipliers,
+                    &max_cost,
+                )?;
+            }
+        }
+
+        let query = request
+            .get("query")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let query_hash = service_config
+            .echo_query_hash
+            .then(|| query_validation::query_hash(query));
+        match query_validation::parse_query(query) {
+            Ok(document) => {
+                metrics::record_query_complexity(&query_validation::complexity(&document));
+
+                if let Some(max) = service_config.query.max_repeated_selections {
+                    query_validation::check_repeated_selections(&document, max)?;
+                }
+
+                if service_config.query.require_variables {
+                    let literals = query_validation::inline_literal_arguments(&document);
+                    if !literals.is_empty() {
+                        return Err(SubgraphServiceError::InlineArgumentLiterals(literals));
+                    }
+                }
+            }
+            Err(e) if service_config.query.require_variables => return Err(e),
+            // Let graph-node report the errors in the query
+            Err(_) => {}
+        }
+
+        if let Some(query) = request.get("query").and_then(Value::as_str) {
+            debug!(
+                %deployment,
+                query = %loggable_query(
+                    query,
+                    service_config.log_query_collapse_whitespace
+                ),
+                "Forwarding query"
+            );
+        }
+
         let deployment_url = Url::parse(&format!(
             "{}/subgraphs/id/{}",
             &self.state.graph_node_query_base_url, deployment
         ))
         .map_err(|_| SubgraphServiceError::InvalidDeployment(deployment))?;
 
-        let response = self
-            .state
-            .graph_node_client
-            .post(deployment_url)
-            .json(&request)
-            .send()
-            .await
-            .map_err(SubgraphServiceError::QueryForwardingError)?;
+        let timeout = upstream::query_timeout(&service_config.upstream, &deployment);
+        let body = serde_json::to_vec(&request).map_err(|e| {
+            SubgraphServiceError::InvalidQuery(anyhow!("Failed to serialize query: {e}"))
+        })?;
+        let signature = service_config
+            .upstream
+            .hmac_signing_key
+            .as_ref()
+            .map(|key| upstream::sign_body(key, &body));
+        timing.record("parse");
+
+        if !self.state.circuit_breaker.allow_request() {
+            return Err(SubgraphServiceError::UpstreamUnavailable);
+        }
+        let _permit = match &self.state.request_queue {
+            Some(queue) => Some(queue.acquire(Priority::from_headers(&headers)).await),
+            None => None,
+        };
+        self.state.error_cooldown.wait().await;
+        let upstream_span = info_span!("upstream", %deployment);
+        let upstream_start = Instant::now();
+        let upstream_config = &service_config.upstream;
+        let forwarded_headers = upstream::forwarded_headers(upstream_config, &headers);
+        let result = upstream::with_backoff(
+            upstream_config.query_max_retries,
+            Duration::from_millis(upstream_config.query_retry_base_delay_ms),
+            || {
+                let mut builder = self
+                    .state
+                    .graph_node_client
+                    .post(deployment_url.clone())
+                    .headers(forwarded_headers.clone())
+                    .header(header::ACCEPT, &service_config.upstream.accept_header)
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .timeout(timeout)
+                    .body(body.clone());
+                if let Some(signature) = &signature {
+                    builder = builder.header(upstream::SIGNATURE_HEADER, signature);
+                }
+                builder.send()
+            },
+        )
+        .instrument(upstream_span.clone())
+        .await;
+        metrics::record_upstream_latency("query", upstream_start.elapsed());
+        let response = result.map_err(|e| {
+            metrics::record_upstream_error("query", metrics::upstream_error_kind(&e));
+            self.state.error_cooldown.record_error();
+            self.state.circuit_breaker.record_failure();
+            self.state.upstream_health.record_failure(&e);
+            SubgraphServiceError::QueryForwardingError(e)
+        })?;
+        if let Some(kind) = metrics::upstream_status_kind(response.status()) {
+            metrics::record_upstream_error("query", kind);
+        }
+        // Server errors are still returned after the last retry
+        if response.status().is_server_error() {
+            self.state.circuit_breaker.record_failure();
+            self.state
+                .upstream_health
+                .record_failure(format!("Graph node responded with {}", response.status()));
+        }
 
+        // Rate limits are passed on whatever the body looks like, so that
+        // clients back off
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(
+                upstream_error_status(response, service_config.sanitize_upstream_errors).await,
+            );
+        }
+
+        // Misconfigured upstreams, e.g. a proxy in front of graph-node, tend
+        // to answer with HTML error pages
+        if let Some(content_type) = response.headers().get(header::CONTENT_TYPE) {
+            let content_type = content_type.to_str().unwrap_or_default();
+            if !content_type.contains("json") {
+                return Err(SubgraphServiceError::UpstreamNonJson(
+                    content_type.to_string(),
+                ));
+            }
+        }
+
+        if !response.status().is_success() {
+            return Err(
+                upstream_error_status(response, service_config.sanitize_upstream_errors).await,
+            );
+        }
+        metrics::record_upstream_success();
+        self.state.circuit_breaker.record_success();
+        self.state.upstream_health.record_success();
+
+        let upstream_status = response.status();
         let attestable = response
             .headers()
             .get("graph-attestable")
             .map_or(false, |value| {
                 value.to_str().map(|value| value == "true").unwrap_or(false)
             });
+        // Overrides can only turn attestation off, never attest responses
+        // graph-node doesn't consider deterministic
+        let attestable = attestable
+            && self
+                .state
+                .attestation_overrides
+                .read()
+                .unwrap()
+                .get(&deployment)
+                .copied()
+                .unwrap_or(true);
 
+        // Reading the body only fails if the connection breaks mid-response;
+        // depending on the response encoding, reqwest reports that as a body
+        // or a decode error
         let body = response
             .text()
+            .instrument(upstream_span)
             .await
-            .map_err(SubgraphServiceError::QueryForwardingError)?;
+            .map_err(|e| {
+                if e.is_body() || e.is_decode() {
+                    SubgraphServiceError::UpstreamConnectionReset(e)
+                } else {
+                    SubgraphServiceError::QueryForwardingError(e)
+                }
+            })?;
+        let upstream_latency = upstream_start.elapsed();
+        timing.record("upstream");
+
+        // Don't sign errors
+        let attestable =
+            attestable && !(service_config.query.no_attest_on_errors && has_graphql_errors(&body));
+        metrics::record_query_response(attestable);
+
+        // Responses that aren't JSON are passed through as-is
+        let sanitize_errors = service_config.sanitize_upstream_errors;
+        let metadata = service_config.query.deployment_metadata.get(&deployment);
+        let body = if self.state.response_transforms.is_empty()
+            && !sanitize_errors
+            && metadata.is_none()
+            && query_hash.is_none()
+        {
+            body
+        } else {
+            match serde_json::from_str::<Value>(&body) {
+                Ok(mut value) => {
+                    if sanitize_errors {
+                        transforms::sanitize_errors(&mut value);
+                    }
+                    self.state.response_transforms.apply(&mut value);
+                    if let Some(metadata) = metadata {
+                        attach_deployment_metadata(&mut value, metadata);
+                    }
+                    if let Some(query_hash) = query_hash {
+                        transforms::insert_extension(&mut value, "queryHash", json!(query_hash));
+                    }
+                    value.to_string()
+                }
+                Err(_) => body,
+            }
+        };
 
-        Ok((request, SubgraphServiceResponse::new(body, attestable)))
+        let mut response_headers = HeaderMap::new();
+        if service_config.query.echo_deployment_header
+            || features.enabled(Feature::EchoDeploymentId)
+        {
+            if let Ok(value) = HeaderValue::from_str(&deployment.to_string()) {
+                response_headers.insert(DEPLOYMENT_ID_HEADER, value);
+            }
+        }
+        if service_config.query.upstream_latency_header {
+            response_headers.insert(
+                UPSTREAM_LATENCY_HEADER,
+                HeaderValue::from(upstream_latency.as_millis() as u64),
+            );
+        }
+        if service_config.server_timing_header {
+            timing.insert_into(&mut response_headers);
+        }
+
+        Ok((
+            request,
+            SubgraphServiceResponse::new(body, attestable, response_headers, upstream_status),
+        ))
     }
 }
 
+/// Error carrying the status of a graph-node response that wasn't successful,
+/// so that it is passed on to the client. The response body is truncated, or
+/// replaced entirely when sanitizing upstream errors.
+async fn upstream_error_status(
+    response: reqwest::Response,
+    sanitize: bool,
+) -> SubgraphServiceError {
+    let status = response.status();
+    let retry_after = response.headers().get(header::RETRY_AFTER).cloned();
+    let body = response.text().await.unwrap_or_default();
+    let message = if sanitize {
+        debug!(%status, body, "Sanitized upstream error");
+        transforms::SANITIZED_ERROR_MESSAGE.to_string()
+    } else {
+        let body = body.trim();
+        match body.char_indices().nth(MAX_UPSTREAM_ERROR_LENGTH) {
+            Some((end, _)) => format!("{}…", &body[..end]),
+            None => body.to_string(),
+        }
+    };
+    SubgraphServiceError::UpstreamErrorStatus {
+        status,
+        message,
+        retry_after,
+    }
+}
+
+/// Whether a GraphQL response body contains errors.
+fn has_graphql_errors(body: &str) -> bool {
+    serde_json::from_str::<Value>(body).is_ok_and(|response| {
+        response
+            .get("errors")
+            .and_then(Value::as_array)
+            .is_some_and(|errors| !errors.is_empty())
+    })
+}
+
+/// Add the deployment's metadata to the `extensions` of a GraphQL response.
+fn attach_deployment_metadata(response: &mut Value, metadata: &HashMap<String, String>) {
+    transforms::insert_extension(response, "deployment", json!(metadata));
+}
+
 /// Run the subgraph indexer service
 pub async fn run() -> anyhow::Result<()> {
     // Parse command line and environment arguments
@@ -124,18 +513,81 @@ pub async fn run() -> anyhow::Result<()> {
     // Load the json-rpc service configuration, which is a combination of the
     // general configuration options for any indexer service and specific
     // options added for JSON-RPC
-    let config =
-        MainConfig::parse(indexer_config::ConfigPrefix::Service, &cli.config).map_err(|e| {
+    let config = match MainConfig::parse(indexer_config::ConfigPrefix::Service, &cli.config) {
+        Ok(config) => config,
+        Err(e) => {
+            logging::init_tracing(None);
             error!(
                 "Invalid configuration file `{}`: {}",
                 cli.config.display(),
                 e
             );
-            anyhow!(e)
-        })?;
+            return Err(anyhow!(e));
+        }
+    };
+    let _trace_guard = logging::init_tracing(config.service.trace_export_file.as_deref());
+    metrics::register_metrics();
 
+    let service_config = config.service.clone();
     let config: Config = config.into();
 
+    let graph_node_client = upstream::build_client(&service_config.upstream)
+        .expect("Failed to init HTTP client for Graph Node");
+    let error_cooldown = upstream::ErrorCooldown::new(&service_config.upstream);
+    let circuit_breaker = upstream::CircuitBreaker::new(&service_config.upstream);
+    let status_cache = StatusCache::new(
+        Duration::from_secs(service_config.status.cache_ttl_secs),
+        Duration::from_millis(service_config.status.cache_refresh_ahead_ms),
+        service_config.status.cache_max_entries,
+        service_config.status.max_concurrent_cache_refreshes,
+    );
+    let status_root_fields =
+        routes::supported_root_fields(&service_config.status.extra_root_fields);
+    let persisted_queries =
+        PersistedQueries::new(service_config.query.persisted_queries_max_entries);
+    let graph_node_status_url = config
+        .0
+        .graph_node
+        .as_ref()
+        .expect("Config must have `common.graph_node.status_url` set")
+        .status_url
+        .clone();
+    let head_tracker = service_config.status.block_poll_interval_secs.map(|secs| {
+        HeadTracker::spawn(
+            graph_node_client.clone(),
+            graph_node_status_url.clone(),
+            Duration::from_secs(secs),
+        )
+    });
+    let request_queue = service_config
+        .query
+        .max_concurrent_queries
+        .map(RequestQueue::new);
+    let response_transforms = ResponseTransforms::new(&service_config.response_transforms)?;
+    let deployment_registry = service_config
+        .query
+        .deployment_registry_url
+        .clone()
+        .map(|url| {
+            DeploymentRegistry::new(
+                url,
+                graph_node_client.clone(),
+                Duration::from_secs(service_config.query.deployment_registry_cache_secs),
+                service_config.query.deployment_registry_cache_max_entries,
+                service_config
+                    .query
+                    .deployment_registry_max_concurrent_lookups,
+            )
+        });
+    let attestation_overrides = match &service_config.attestation_overrides_file {
+        Some(path) => attestation_overrides::watch(path.clone())?,
+        None => Default::default(),
+    };
+    let cost_model_files = match &service_config.cost_models_dir {
+        Some(dir) => cost_model_files::watch(dir.clone())?,
+        None => Default::default(),
+    };
+
     // Parse basic configurations
     build_info::build_info!(fn build_info);
     let release = IndexerServiceRelease::from(build_info());
@@ -145,20 +597,27 @@ pub async fn run() -> anyhow::Result<()> {
     // that is involved in serving requests
     let state = Arc::new(SubgraphServiceState {
         config: config.clone(),
+        service_config: RwLock::new(Arc::new(service_config)),
+        config_file: cli.config.clone(),
         database: database::connect(&config.0.database.postgres_url).await,
         cost_schema: routes::cost::build_schema().await,
-        graph_node_client: reqwest::ClientBuilder::new()
-            .tcp_nodelay(true)
-            .timeout(Duration::from_secs(30))
-            .build()
-            .expect("Failed to init HTTP client for Graph Node"),
-        graph_node_status_url: config
-            .0
-            .graph_node
-            .as_ref()
-            .expect("Config must have `common.graph_node.status_url` set")
-            .status_url
-            .clone(),
+        cost_model_files,
+        cost_models: Default::default(),
+        graph_node_client,
+        error_cooldown,
+        circuit_breaker,
+        upstream_health: Default::default(),
+        status_log: Default::default(),
+        status_cache,
+        status_root_fields,
+        persisted_queries,
+        request_queue,
+        attestation_overrides,
+        response_transforms,
+        deployment_registry,
+        head_tracker,
+        release: release.clone(),
+        graph_node_status_url,
         graph_node_query_base_url: config
             .0
             .graph_node
@@ -168,16 +627,1060 @@ pub async fn run() -> anyhow::Result<()> {
             .clone(),
     });
 
+    if let Some(query) = &state.service_config().upstream.startup_validation_query {
+        if let Err(e) = upstream::validate_upstream(
+            &state.graph_node_client,
+            &state.graph_node_status_url,
+            query,
+            &state.service_config().upstream.startup_validation_expected,
+        )
+        .await
+        {
+            if state.service_config().upstream.startup_validation_required {
+                return Err(e.context("Graph node failed the startup validation query"));
+            }
+            warn!(error = %e, "Graph node failed the startup validation query");
+        }
+    }
+
+    if let Some(required) = &state.service_config().upstream.required_version {
+        match upstream::check_version(
+            &state.graph_node_client,
+            &state.graph_node_status_url,
+            required,
+        )
+        .await
+        {
+            Ok(version) => info!(%version, %required, "Graph node version is supported"),
+            Err(e) if state.service_config().upstream.required_version_strict => {
+                return Err(e.context("Graph node version is not supported"));
+            }
+            Err(e) => warn!(error = %e, "Graph node version is not supported"),
+        }
+    }
+
+    if let Some(address) = &state.service_config().statsd_address {
+        statsd::spawn_exporter(address.clone(), "subgraph_");
+    }
+
     IndexerService::run(IndexerServiceOptions {
         release,
         config: config.0.clone(),
         url_namespace: "subgraphs",
         metrics_prefix: "subgraph",
         service_impl: SubgraphService::new(state.clone()),
-        extra_routes: Router::new()
-            .route("/cost", post(routes::cost::cost))
-            .route("/status", post(routes::status))
-            .with_state(state),
+        extra_routes: extra_routes(state),
     })
     .await
 }
+
+/// Routes served next to the common indexer service routes
+fn extra_routes<S>(state: Arc<SubgraphServiceState>) -> Router<S> {
+    // Protected by the same token as the common health routes
+    let mut health_routes = Router::new()
+        .route("/health", get(routes::health))
+        .route("/ready", get(routes::ready));
+    if let Some(token) = state.service_config().health_token.clone() {
+        health_routes = health_routes.route_layer(middleware::from_fn_with_state(
+            Arc::new(token),
+            require_health_token,
+        ));
+    }
+
+    let mut router = Router::new()
+        .route("/status", post(routes::status).head(routes::status_head))
+        .route("/status/deployments", get(routes::deployments_status))
+        .route("/status/:version", post(routes::versioned_status))
+        .merge(health_routes);
+    if state.service_config().enable_cost_route {
+        router = router.route("/cost", post(routes::cost::cost));
+    }
+    if state
+        .service_config()
+        .admin_token
+        .as_ref()
+        .is_some_and(|token| !token.is_empty())
+    {
+        router = router.route("/admin/reload", post(routes::admin::reload_config));
+    }
+    router.with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    use axum::{body::Body, http::Request, response::IntoResponse};
+    use tempfile::NamedTempFile;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+    use wiremock::{
+        matchers::{body_partial_json, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::{features::FEATURES_HEADER, test_utils};
+
+    use super::*;
+
+    const DEPLOYMENT: &str = "QmAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+
+    async fn mock_graph_node() -> MockServer {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} }))),
+            )
+            .await;
+        graph_node
+    }
+
+    #[tokio::test]
+    async fn test_echo_deployment_header() {
+        let graph_node = mock_graph_node().await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().query.echo_deployment_header = true;
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let (_, response) = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap();
+
+        let (headers, _, _) = response.finalize(None);
+        assert_eq!(headers[DEPLOYMENT_ID_HEADER], DEPLOYMENT);
+    }
+
+    #[tokio::test]
+    async fn test_status_and_query_use_their_own_retries() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(500).set_body_string("Internal error")),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let upstream = &mut state.service_config_mut().upstream;
+        upstream.status_max_retries = 2;
+        upstream.query_max_retries = 1;
+        upstream.query_retry_base_delay_ms = 1;
+        let state = Arc::new(state);
+        let hits = |path: String| {
+            let graph_node = &graph_node;
+            async move {
+                graph_node
+                    .received_requests()
+                    .await
+                    .unwrap()
+                    .iter()
+                    .filter(|request| request.url.path() == path)
+                    .count()
+            }
+        };
+
+        let app = Router::new()
+            .route("/status", post(routes::status::status))
+            .with_state(state.clone());
+        let body = Body::from(json!({ "query": "{ chains { network } }" }).to_string());
+        app.oneshot(Request::post("/status").body(body).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(hits("/graphql".to_string()).await, 3);
+
+        let service = SubgraphService::new(state);
+        let _ = service
+            .process_request(
+                DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                HeaderMap::new(),
+            )
+            .await;
+        assert_eq!(hits(format!("/subgraphs/id/{DEPLOYMENT}")).await, 2);
+        // The status query wasn't sent again
+        assert_eq!(hits("/graphql".to_string()).await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_deployment_timeouts() {
+        const SLOW_DEPLOYMENT: &str = "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB";
+
+        let graph_node = MockServer::start().await;
+        for deployment in [DEPLOYMENT, SLOW_DEPLOYMENT] {
+            graph_node
+                .register(
+                    Mock::given(method("POST"))
+                        .and(path(format!("/subgraphs/id/{deployment}")))
+                        .respond_with(
+                            ResponseTemplate::new(200)
+                                .set_body_json(json!({ "data": {} }))
+                                .set_delay(Duration::from_millis(1500)),
+                        ),
+                )
+                .await;
+        }
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().upstream.timeout_secs = 1;
+        state
+            .service_config_mut()
+            .upstream
+            .deployment_timeouts
+            .insert(DeploymentId::from_str(SLOW_DEPLOYMENT).unwrap(), 5);
+        let service = SubgraphService::new(Arc::new(state));
+
+        let result = service
+            .process_request(
+                DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                HeaderMap::new(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(SubgraphServiceError::QueryForwardingError(e)) if e.is_timeout()
+        ));
+
+        let result = service
+            .process_request(
+                DeploymentId::from_str(SLOW_DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                HeaderMap::new(),
+            )
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connection_reset_mid_body() {
+        // Announce a longer body than is sent, then drop the connection
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 100\r\n\r\n{\"data\":")
+                .await
+                .unwrap();
+        });
+
+        let state = test_utils::subgraph_service_state(&format!("http://{address}")).await;
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let error = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SubgraphServiceError::UpstreamConnectionReset(_)
+        ));
+        assert_eq!(StatusCode::from(&error), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_deployments_are_gone() {
+        const DEPRECATED_DEPLOYMENT: &str = "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB";
+
+        let graph_node = mock_graph_node().await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().query.deprecated_deployments =
+            vec![DeploymentId::from_str(DEPRECATED_DEPLOYMENT).unwrap()];
+        let service = SubgraphService::new(Arc::new(state));
+
+        let error = service
+            .process_request(
+                DeploymentId::from_str(DEPRECATED_DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(StatusCode::from(&error), StatusCode::GONE);
+
+        let result = service
+            .process_request(
+                DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                HeaderMap::new(),
+            )
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_deployment_allow_and_deny_lists() {
+        const OTHER_DEPLOYMENT: &str = "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB";
+
+        let graph_node = mock_graph_node().await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().query.allowed_deployments = vec![
+            DeploymentId::from_str(DEPLOYMENT).unwrap(),
+            DeploymentId::from_str(OTHER_DEPLOYMENT).unwrap(),
+        ];
+        state.service_config_mut().query.denied_deployments =
+            vec![DeploymentId::from_str(OTHER_DEPLOYMENT).unwrap()];
+        let service = SubgraphService::new(Arc::new(state));
+
+        let query = |deployment: &str| {
+            service.process_request(
+                DeploymentId::from_str(deployment).unwrap(),
+                json!({ "query": "{ a }" }),
+                HeaderMap::new(),
+            )
+        };
+
+        assert!(query(DEPLOYMENT).await.is_ok());
+        // The deny list wins over the allow list
+        let error = query(OTHER_DEPLOYMENT).await.unwrap_err();
+        assert_eq!(StatusCode::from(&error), StatusCode::FORBIDDEN);
+        // Deployments missing from a non-empty allow list are rejected
+        let error = query("QmCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCCC")
+            .await
+            .unwrap_err();
+        assert_eq!(StatusCode::from(&error), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_persisted_queries_are_expanded() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .and(body_partial_json(json!({ "query": "{ a }" })))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} })))
+                    .expect(2),
+            )
+            .await;
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        // sha256 of `{ a }`
+        let extensions = json!({
+            "persistedQuery": {
+                "version": 1,
+                "sha256Hash": "1c7e1e347f726166b5b1c55afd61f278cc9b45e00c108ec33d540a566379811b",
+            }
+        });
+        let hash_only = json!({ "extensions": extensions });
+
+        let error = service
+            .process_request(deployment, hash_only.clone(), HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), "PERSISTED_QUERY_NOT_FOUND");
+
+        service
+            .process_request(
+                deployment,
+                json!({ "query": "{ a }", "extensions": extensions }),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+        let (request, _) = service
+            .process_request(deployment, hash_only, HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(request["query"], "{ a }");
+    }
+
+    #[tokio::test]
+    async fn test_compressed_upstream_responses() {
+        let body = r#"{"data":{"tokens":[]}}"#;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(body.as_bytes()).unwrap();
+
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .and(header_exists("accept-encoding"))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .insert_header("content-type", "application/json")
+                            .insert_header("content-encoding", "gzip")
+                            .insert_header("graph-attestable", "true")
+                            .set_body_bytes(encoder.finish().unwrap()),
+                    ),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().upstream.compress = true;
+        state.graph_node_client = upstream::build_client(&state.service_config().upstream).unwrap();
+        let service = SubgraphService::new(Arc::new(state));
+
+        let (_, response) = service
+            .process_request(
+                DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                json!({ "query": "{ tokens { id } }" }),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+        assert!(response.is_attestable());
+        assert_eq!(response.as_str().unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn test_query_hash_is_echoed() {
+        let graph_node = mock_graph_node().await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().echo_query_hash = true;
+        let service = SubgraphService::new(Arc::new(state));
+
+        let (_, response) = service
+            .process_request(
+                DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(response.as_str().unwrap()).unwrap();
+        assert_eq!(
+            body["extensions"]["queryHash"],
+            query_validation::query_hash("{ a }")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_trace_context_is_forwarded() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .and(header("traceparent", "00-abc-def-01"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} })))
+                    .expect(1),
+            )
+            .await;
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let service = SubgraphService::new(Arc::new(state));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", HeaderValue::from_static("00-abc-def-01"));
+        service
+            .process_request(
+                DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                headers,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_breaker_fails_fast() {
+        // Nothing listens on port 1
+        let mut state = test_utils::subgraph_service_state("http://127.0.0.1:1").await;
+        state
+            .service_config_mut()
+            .upstream
+            .circuit_breaker_threshold = Some(1);
+        state.circuit_breaker = upstream::CircuitBreaker::new(&state.service_config().upstream);
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let error = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SubgraphServiceError::QueryForwardingError(_)
+        ));
+
+        // Fails without trying to reach graph-node
+        let error = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(StatusCode::from(&error), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_open_circuit_breaker() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .respond_with(ResponseTemplate::new(500).set_body_json(json!({ "errors": [] })))
+                    .expect(1),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().upstream.query_max_retries = 0;
+        state
+            .service_config_mut()
+            .upstream
+            .circuit_breaker_threshold = Some(1);
+        state.circuit_breaker = upstream::CircuitBreaker::new(&state.service_config().upstream);
+        let state = Arc::new(state);
+        let service = SubgraphService::new(state.clone());
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let error = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(StatusCode::from(&error), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(state
+            .upstream_health
+            .recent_failure(Duration::from_secs(60))
+            .is_some());
+
+        // Fails without trying to reach graph-node
+        let error = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert_eq!(StatusCode::from(&error), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_responses_with_errors_are_not_attestable() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .insert_header("graph-attestable", "true")
+                            .set_body_json(json!({
+                                "data": null,
+                                "errors": [{ "message": "store error" }],
+                            })),
+                    ),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let query = json!({ "query": "{ a }" });
+
+        state.service_config_mut().query.no_attest_on_errors = false;
+        let state = Arc::new(state);
+        let (_, response) = SubgraphService::new(state.clone())
+            .process_request(deployment, query.clone(), HeaderMap::new())
+            .await
+            .unwrap();
+        assert!(response.is_attestable());
+
+        let mut state = Arc::into_inner(state).unwrap();
+        state.service_config_mut().query.no_attest_on_errors = true;
+        let (_, response) = SubgraphService::new(Arc::new(state))
+            .process_request(deployment, query, HeaderMap::new())
+            .await
+            .unwrap();
+        assert!(!response.is_attestable());
+    }
+
+    #[test]
+    fn test_has_graphql_errors() {
+        assert!(has_graphql_errors(r#"{"errors":[{"message":"oops"}]}"#));
+        assert!(!has_graphql_errors(r#"{"data":{},"errors":[]}"#));
+        assert!(!has_graphql_errors(r#"{"data":{}}"#));
+        assert!(!has_graphql_errors("not json"));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_status_is_preserved() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(header("x-case", "rate-limited"))
+                    .respond_with(
+                        ResponseTemplate::new(429)
+                            .insert_header("retry-after", "5")
+                            .set_body_raw("Too many requests", "text/plain"),
+                    ),
+            )
+            .await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(header("x-case", "bad-request"))
+                    .respond_with(
+                        ResponseTemplate::new(400)
+                            .set_body_json(json!({ "errors": [{ "message": "Unknown field" }] })),
+                    ),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().upstream.forward_headers = vec!["x-case".to_string()];
+        let service = SubgraphService::new(Arc::new(state));
+
+        let send = |case: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-case", HeaderValue::from_static(case));
+            service.process_request(
+                DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                headers,
+            )
+        };
+
+        let error = send("rate-limited").await.unwrap_err();
+        assert_eq!(StatusCode::from(&error), StatusCode::TOO_MANY_REQUESTS);
+        let response = error.into_response();
+        assert_eq!(response.headers()["retry-after"], "5");
+
+        let error = send("bad-request").await.unwrap_err();
+        assert_eq!(StatusCode::from(&error), StatusCode::BAD_REQUEST);
+        assert!(error.to_string().contains("Unknown field"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_headers_are_stripped_and_limited() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(header("traceparent", "00-abc-def-01"))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} })))
+                    .expect(1),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let upstream = &mut state.service_config_mut().upstream;
+        upstream.forward_headers = ["cookie", "authorization", "traceparent", "x-large"]
+            .map(String::from)
+            .into();
+        upstream.max_forwarded_header_bytes = 100;
+        let service = SubgraphService::new(Arc::new(state));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", HeaderValue::from_static("session=secret"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("traceparent", HeaderValue::from_static("00-abc-def-01"));
+        headers.insert("x-large", HeaderValue::from_str(&"x".repeat(200)).unwrap());
+        service
+            .process_request(
+                DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                json!({ "query": "{ a }" }),
+                headers,
+            )
+            .await
+            .unwrap();
+
+        let requests = graph_node.received_requests().await.unwrap();
+        for (name, _) in &requests[0].headers {
+            assert!(
+                !["cookie", "authorization", "x-large"].contains(&name.as_str()),
+                "{name} was forwarded"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upstream_error_bodies_are_not_leaked() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST")).respond_with(
+                    ResponseTemplate::new(429)
+                        .insert_header("retry-after", "5")
+                        .set_body_raw(
+                            format!("<html>internal proxy 10.0.0.1{}</html>", "x".repeat(2000)),
+                            "text/html",
+                        ),
+                ),
+            )
+            .await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let send = |state: SubgraphServiceState| async move {
+            SubgraphService::new(Arc::new(state))
+                .process_request(
+                    DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                    json!({ "query": "{ a }" }),
+                    HeaderMap::new(),
+                )
+                .await
+                .unwrap_err()
+        };
+
+        // Long bodies are truncated
+        let error = send(test_utils::subgraph_service_state(&graph_node.uri()).await).await;
+        let SubgraphServiceError::UpstreamErrorStatus { message, .. } = &error else {
+            panic!("unexpected error: {error}");
+        };
+        assert_eq!(message.chars().count(), MAX_UPSTREAM_ERROR_LENGTH + 1);
+
+        // Sanitized bodies are dropped, the status is kept
+        state.service_config_mut().sanitize_upstream_errors = true;
+        let error = send(state).await;
+        assert_eq!(StatusCode::from(&error), StatusCode::TOO_MANY_REQUESTS);
+        assert!(!error.to_string().contains("10.0.0.1"), "{error}");
+        let response = error.into_response();
+        assert_eq!(response.headers()["retry-after"], "5");
+    }
+
+    #[tokio::test]
+    async fn test_health_routes_require_health_token() {
+        let mut state = test_utils::subgraph_service_state("http://graph-node.invalid").await;
+        state.service_config_mut().health_token = Some("health-token".to_string());
+        let app: Router = extra_routes(Arc::new(state));
+
+        for path in ["/health", "/ready"] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{path}");
+
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::get(path)
+                        .header(header::AUTHORIZATION, "Bearer health-token")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cost_route_can_be_disabled() {
+        let mut state = test_utils::subgraph_service_state("http://graph-node.invalid").await;
+        state.service_config_mut().enable_cost_route = false;
+        let app: Router = extra_routes(Arc::new(state));
+
+        let response = app
+            .oneshot(
+                Request::post("/cost")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        r#"{"query": "{ costModels(deployments: []) { deployment } }"}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_accept_header() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .and(wiremock::matchers::header(
+                        "accept",
+                        "application/graphql-response+json",
+                    ))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} })))
+                    .expect(1),
+            )
+            .await;
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().upstream.accept_header =
+            "application/graphql-response+json".to_string();
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_body_is_signed() {
+        let body = json!({ "query": "{ a }" });
+        let signature = upstream::sign_body("secret", body.to_string().as_bytes());
+
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .and(wiremock::matchers::header(
+                        upstream::SIGNATURE_HEADER,
+                        signature.as_str(),
+                    ))
+                    .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "data": {} })))
+                    .expect(1),
+            )
+            .await;
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().upstream.hmac_signing_key = Some("secret".to_string());
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        service
+            .process_request(deployment, body, HeaderMap::new())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_html_upstream_response() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .respond_with(
+                        ResponseTemplate::new(502)
+                            .set_body_raw("<html><body>502 Bad Gateway</body></html>", "text/html"),
+                    ),
+            )
+            .await;
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let error = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, SubgraphServiceError::UpstreamNonJson(content_type) if content_type == "text/html"),
+            "{error}"
+        );
+        assert_eq!(StatusCode::from(&error), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn test_deployment_metadata_extension() {
+        let graph_node = mock_graph_node().await;
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        state.service_config_mut().query.deployment_metadata.insert(
+            deployment,
+            HashMap::from([
+                ("network".to_string(), "mainnet".to_string()),
+                ("schema_version".to_string(), "1.2.0".to_string()),
+            ]),
+        );
+        let service = SubgraphService::new(Arc::new(state));
+
+        let (_, response) = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_str(response.as_str().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "data": {},
+                "extensions": {
+                    "deployment": { "network": "mainnet", "schema_version": "1.2.0" }
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_latency_header() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_json(json!({ "data": {} }))
+                            .set_delay(Duration::from_millis(100)),
+                    ),
+            )
+            .await;
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.service_config_mut().query.upstream_latency_header = true;
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let (_, response) = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap();
+
+        let (headers, _, _) = response.finalize(None);
+        let latency: u64 = headers[UPSTREAM_LATENCY_HEADER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((100..5000).contains(&latency), "{latency}");
+    }
+
+    #[tokio::test]
+    async fn test_feature_flag_only_applies_to_its_request() {
+        let graph_node = mock_graph_node().await;
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        assert!(!state.service_config().query.echo_deployment_header);
+        let service = SubgraphService::new(Arc::new(state));
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            FEATURES_HEADER,
+            HeaderValue::from_static("echo-deployment-id"),
+        );
+        let (_, response) = service
+            .process_request(deployment, json!({ "query": "{ a }" }), headers)
+            .await
+            .unwrap();
+        let (headers, _, _) = response.finalize(None);
+        assert_eq!(headers[DEPLOYMENT_ID_HEADER], DEPLOYMENT);
+
+        let (_, response) = service
+            .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+            .await
+            .unwrap();
+        let (headers, _, _) = response.finalize(None);
+        assert!(!headers.contains_key(DEPLOYMENT_ID_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_attestation_overrides_file_is_reloaded() {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path(format!("/subgraphs/id/{DEPLOYMENT}")))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .insert_header("graph-attestable", "true")
+                            .set_body_json(json!({ "data": {} })),
+                    ),
+            )
+            .await;
+
+        let write_overrides = |file: &mut NamedTempFile, attest: bool| {
+            let file = file.as_file_mut();
+            file.set_len(0).unwrap();
+            write!(file, r#"{{ "{DEPLOYMENT}": {attest} }}"#).unwrap();
+            file.set_modified(SystemTime::now() + Duration::from_secs(1))
+                .unwrap();
+        };
+        let mut file = NamedTempFile::new().unwrap();
+        write_overrides(&mut file, false);
+
+        let mut state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.attestation_overrides = attestation_overrides::watch_with_interval(
+            file.path().to_path_buf(),
+            Duration::from_millis(10),
+        )
+        .unwrap();
+        let service = SubgraphService::new(Arc::new(state));
+
+        let deployment = DeploymentId::from_str(DEPLOYMENT).unwrap();
+        let is_attestable = || async {
+            let (_, response) = service
+                .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+                .await
+                .unwrap();
+            response.is_attestable()
+        };
+        assert!(!is_attestable().await);
+
+        write_overrides(&mut file, true);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !is_attestable().await {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("updated overrides should apply without a restart");
+    }
+
+    #[tokio::test]
+    async fn test_attestation_overrides_only_disable_attestation() {
+        const OTHER_DEPLOYMENT: &str = "QmBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB";
+        let graph_node = MockServer::start().await;
+        for (deployment, attestable) in [(DEPLOYMENT, "true"), (OTHER_DEPLOYMENT, "false")] {
+            graph_node
+                .register(
+                    Mock::given(method("POST"))
+                        .and(path(format!("/subgraphs/id/{deployment}")))
+                        .respond_with(
+                            ResponseTemplate::new(200)
+                                .insert_header("graph-attestable", attestable)
+                                .set_body_json(json!({ "data": {} })),
+                        ),
+                )
+                .await;
+        }
+
+        let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+        state.attestation_overrides.write().unwrap().extend([
+            (DeploymentId::from_str(DEPLOYMENT).unwrap(), false),
+            (DeploymentId::from_str(OTHER_DEPLOYMENT).unwrap(), true),
+        ]);
+        let service = SubgraphService::new(Arc::new(state));
+
+        let is_attestable = |deployment: &str| {
+            let deployment = DeploymentId::from_str(deployment).unwrap();
+            let service = &service;
+            async move {
+                let (_, response) = service
+                    .process_request(deployment, json!({ "query": "{ a }" }), HeaderMap::new())
+                    .await
+                    .unwrap();
+                response.is_attestable()
+            }
+        };
+        // Attestable responses are no longer attested
+        assert!(!is_attestable(DEPLOYMENT).await);
+        // Responses graph-node doesn't mark attestable stay unattested
+        assert!(!is_attestable(OTHER_DEPLOYMENT).await);
+    }
+
+    #[tokio::test]
+    async fn test_request_produces_chrome_trace() {
+        let graph_node = mock_graph_node().await;
+
+        let trace_file = NamedTempFile::new().unwrap();
+        let (layer, guard) = logging::chrome_layer(trace_file.path());
+        let subscriber = tracing_subscriber::registry().with(layer);
+        {
+            let _default = tracing::subscriber::set_default(subscriber);
+            let state = test_utils::subgraph_service_state(&graph_node.uri()).await;
+            let service = SubgraphService::new(Arc::new(state));
+            service
+                .process_request(
+                    DeploymentId::from_str(DEPLOYMENT).unwrap(),
+                    json!({ "query": "{ a }" }),
+                    HeaderMap::new(),
+                )
+                .await
+                .unwrap();
+        }
+        drop(guard);
+
+        let trace = std::fs::read_to_string(trace_file.path()).unwrap();
+        let entries: Vec<Value> = serde_json::from_str(&trace).unwrap();
+        assert!(entries.iter().all(|entry| entry["ph"].is_string()));
+        assert!(entries
+            .iter()
+            .any(|entry| entry["name"].as_str() == Some("upstream") && entry["ts"].is_number()));
+    }
+}
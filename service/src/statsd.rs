@@ -0,0 +1,140 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{collections::HashMap, time::Duration};
+
+use prometheus::proto::{MetricFamily, MetricType};
+use tokio::net::UdpSocket;
+use tracing::warn;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Periodically mirror all Prometheus metrics starting with `prefix` to a
+/// StatsD/DogStatsD server. Counters are sent as increments since the last
+/// flush, gauges as their current value; labels become DogStatsD tags.
+pub fn spawn_exporter(address: String, prefix: &'static str) {
+    tokio::spawn(async move {
+        let socket = match connect(&address).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!(%address, error = %e, "Failed to set up StatsD exporter");
+                return;
+            }
+        };
+
+        let mut counters = HashMap::new();
+        let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+        loop {
+            interval.tick().await;
+            flush(&socket, prefix, &mut counters).await;
+        }
+    });
+}
+
+async fn connect(address: &str) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(address).await?;
+    Ok(socket)
+}
+
+async fn flush(socket: &UdpSocket, prefix: &str, counters: &mut HashMap<String, f64>) {
+    for line in encode(&prometheus::gather(), prefix, counters) {
+        if let Err(e) = socket.send(line.as_bytes()).await {
+            warn!(error = %e, "Failed to send metrics to StatsD");
+            return;
+        }
+    }
+}
+
+/// Encode metric families as StatsD lines, remembering the last counter values
+/// in `counters` so only increments are sent.
+fn encode(
+    families: &[MetricFamily],
+    prefix: &str,
+    counters: &mut HashMap<String, f64>,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    for family in families {
+        let name = family.get_name();
+        if !name.starts_with(prefix) {
+            continue;
+        }
+
+        for metric in family.get_metric() {
+            let tags = metric
+                .get_label()
+                .iter()
+                .map(|label| format!("{}:{}", label.get_name(), label.get_value()))
+                .collect::<Vec<_>>();
+            let tags = if tags.is_empty() {
+                String::new()
+            } else {
+                format!("|#{}", tags.join(","))
+            };
+
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let value = metric.get_counter().get_value();
+                    let last = counters.insert(format!("{name}{tags}"), value);
+                    let delta = value - last.unwrap_or_default();
+                    if delta > 0.0 {
+                        lines.push(format!("{name}:{delta}|c{tags}"));
+                    }
+                }
+                MetricType::GAUGE => {
+                    let value = metric.get_gauge().get_value();
+                    lines.push(format!("{name}:{value}|g{tags}"));
+                }
+                // Histograms and summaries have no StatsD equivalent that can
+                // be derived from aggregated buckets
+                _ => {}
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::STATUS_SLO_VIOLATIONS;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_metrics_are_sent_to_statsd() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let socket = connect(&receiver.local_addr().unwrap().to_string())
+            .await
+            .unwrap();
+
+        STATUS_SLO_VIOLATIONS.inc();
+        flush(&socket, "subgraph_", &mut HashMap::new()).await;
+
+        let mut buf = [0u8; 1024];
+        let found = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let len = receiver.recv(&mut buf).await.unwrap();
+                let line = String::from_utf8_lossy(&buf[..len]).to_string();
+                if line.starts_with("subgraph_status_slo_violations_total:") {
+                    return line;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert!(found.ends_with("|c"));
+    }
+
+    #[test]
+    fn test_counters_are_sent_as_increments() {
+        STATUS_SLO_VIOLATIONS.inc();
+        let mut counters = HashMap::new();
+        let families = prometheus::gather();
+
+        let first = encode(&families, "subgraph_status_slo", &mut counters);
+        assert_eq!(first.len(), 1);
+
+        // Nothing changed since the last flush
+        assert!(encode(&families, "subgraph_status_slo", &mut counters).is_empty());
+    }
+}
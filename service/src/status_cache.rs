@@ -0,0 +1,259 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use graphql::graphql_parser::query as q;
+use serde_json::Value;
+use tokio::sync::{OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+
+/// Successful status query results, kept for a short while so that repeated
+/// polling doesn't reach graph-node every time.
+pub struct StatusCache {
+    ttl: Duration,
+    refresh_ahead: Duration,
+    max_entries: usize,
+    entries: RwLock<HashMap<String, Entry>>,
+    refreshes: Arc<Semaphore>,
+    /// Keys that are being fetched after a cache miss
+    fetches: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+struct Entry {
+    data: Value,
+    cached_at: Instant,
+    refreshing: bool,
+}
+
+impl StatusCache {
+    /// A zero `ttl` disables caching. Entries are due for a refresh once they
+    /// are within `refresh_ahead` of expiring; zero disables refreshing. Once
+    /// `max_entries` are cached, the oldest entry makes room for a new one. At
+    /// most `max_concurrent_refreshes` entries are refreshed at a time.
+    pub fn new(
+        ttl: Duration,
+        refresh_ahead: Duration,
+        max_entries: usize,
+        max_concurrent_refreshes: usize,
+    ) -> Self {
+        Self {
+            ttl,
+            refresh_ahead,
+            max_entries,
+            entries: RwLock::new(HashMap::new()),
+            refreshes: Arc::new(Semaphore::new(max_concurrent_refreshes)),
+            fetches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Cache key of a query, independent of how the query is formatted. The
+    /// operation name is part of the key, as it picks which operation of the
+    /// document is run.
+    pub fn key(
+        document: &q::Document<'_, String>,
+        operation_name: Option<&str>,
+        variables: &async_graphql::Variables,
+    ) -> String {
+        format!(
+            "{document}{}{}",
+            serde_json::to_string(&operation_name).unwrap_or_default(),
+            serde_json::to_string(variables).unwrap_or_default()
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<Value> {
+        match self.entries.read().unwrap().get(key) {
+            Some(entry) if entry.cached_at.elapsed() < self.ttl => Some(entry.data.clone()),
+            _ => None,
+        }
+    }
+
+    /// A permit to refresh the entry if it is about to expire. Only one caller
+    /// per entry gets one, until the entry is replaced, and none while too
+    /// many other entries are being refreshed. The permit is to be held until
+    /// the refresh is done.
+    pub fn claim_refresh(&self, key: &str) -> Option<OwnedSemaphorePermit> {
+        if self.refresh_ahead.is_zero() {
+            return None;
+        }
+        match self.entries.write().unwrap().get_mut(key) {
+            Some(entry)
+                if !entry.refreshing
+                    && entry.cached_at.elapsed() + self.refresh_ahead >= self.ttl =>
+            {
+                let permit = self.refreshes.clone().try_acquire_owned().ok()?;
+                entry.refreshing = true;
+                Some(permit)
+            }
+            _ => None,
+        }
+    }
+
+    /// Wait for the right to fetch an entry after a cache miss, so that
+    /// concurrent misses on one key share a single upstream query: the cache
+    /// is to be checked again once the guard is held. Returns `None` if
+    /// caching is disabled, as the result would not be shared.
+    pub async fn lock_fetch(&self, key: &str) -> Option<FetchGuard<'_>> {
+        if self.ttl.is_zero() || self.max_entries == 0 {
+            return None;
+        }
+        let lock = self
+            .fetches
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        Some(FetchGuard {
+            cache: self,
+            key: key.to_string(),
+            guard: Some(lock.lock_owned().await),
+        })
+    }
+
+    pub fn insert(&self, key: String, data: Value) {
+        if self.ttl.is_zero() || self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.write().unwrap();
+        entries.retain(|_, entry| entry.cached_at.elapsed() < self.ttl);
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.cached_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                data,
+                cached_at: Instant::now(),
+                refreshing: false,
+            },
+        );
+    }
+}
+
+/// Held while an entry is fetched after a cache miss.
+pub struct FetchGuard<'a> {
+    cache: &'a StatusCache,
+    key: String,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for FetchGuard<'_> {
+    fn drop(&mut self) {
+        let mut fetches = self.cache.fetches.lock().unwrap();
+        // Forget the key unless other requests are waiting for it
+        if let Some(lock) = fetches.get(&self.key) {
+            if Arc::strong_count(lock) <= 2 {
+                fetches.remove(&self.key);
+            }
+        }
+        self.guard.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn key(query: &str) -> String {
+        let document = q::parse_query::<String>(query).unwrap();
+        StatusCache::key(&document, None, &Default::default())
+    }
+
+    #[test]
+    fn test_key_ignores_formatting() {
+        assert_eq!(
+            key("{ indexingStatuses { subgraph } }"),
+            key("query {\n  indexingStatuses {\n    subgraph\n  }\n}")
+        );
+        assert_ne!(
+            key("{ indexingStatuses { subgraph } }"),
+            key("{ indexingStatuses { health } }")
+        );
+    }
+
+    #[test]
+    fn test_key_includes_operation_name() {
+        let document = q::parse_query::<String>(
+            "query Chains { chains { network } } query Statuses { indexingStatuses { subgraph } }",
+        )
+        .unwrap();
+        assert_ne!(
+            StatusCache::key(&document, Some("Chains"), &Default::default()),
+            StatusCache::key(&document, Some("Statuses"), &Default::default())
+        );
+    }
+
+    #[test]
+    fn test_oldest_entry_is_evicted() {
+        let cache = StatusCache::new(Duration::from_secs(60), Duration::ZERO, 2, 1);
+        for query in ["first", "second", "third"] {
+            cache.insert(query.to_string(), json!({}));
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(cache.get("first"), None);
+        assert_eq!(cache.get("second"), Some(json!({})));
+        assert_eq!(cache.get("third"), Some(json!({})));
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = StatusCache::new(Duration::from_millis(50), Duration::ZERO, 10, 1);
+        cache.insert("query".to_string(), json!({ "chains": [] }));
+        assert_eq!(cache.get("query"), Some(json!({ "chains": [] })));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get("query"), None);
+    }
+
+    #[test]
+    fn test_zero_ttl_disables_cache() {
+        let cache = StatusCache::new(Duration::ZERO, Duration::ZERO, 10, 1);
+        cache.insert("query".to_string(), json!({}));
+        assert_eq!(cache.get("query"), None);
+    }
+
+    #[test]
+    fn test_refresh_is_claimed_once_near_expiry() {
+        let cache = StatusCache::new(Duration::from_millis(100), Duration::from_millis(50), 10, 1);
+        cache.insert("query".to_string(), json!({}));
+        assert!(cache.claim_refresh("query").is_none());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.claim_refresh("query").is_some());
+        assert!(cache.claim_refresh("query").is_none());
+        assert!(cache.claim_refresh("other").is_none());
+
+        // A refreshed entry can be refreshed again once it's about to expire
+        cache.insert("query".to_string(), json!({}));
+        assert!(cache.claim_refresh("query").is_none());
+    }
+
+    #[test]
+    fn test_concurrent_refreshes_are_limited() {
+        let cache = StatusCache::new(Duration::from_millis(100), Duration::from_millis(50), 10, 1);
+        cache.insert("first".to_string(), json!({}));
+        cache.insert("second".to_string(), json!({}));
+        std::thread::sleep(Duration::from_millis(60));
+
+        let permit = cache.claim_refresh("first");
+        assert!(permit.is_some());
+        assert!(cache.claim_refresh("second").is_none());
+
+        // Once the first refresh is done, the second entry can be refreshed
+        drop(permit);
+        assert!(cache.claim_refresh("second").is_some());
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use indexer_common::indexer_service::http::IndexerServiceRelease;
+use indexer_config::{Config as MainConfig, ConfigPrefix, ServiceConfig};
+use sqlx::postgres::PgPoolOptions;
+
+use crate::{
+    persisted_queries::PersistedQueries, routes, service::SubgraphServiceState,
+    status_cache::StatusCache, upstream,
+};
+
+/// The minimal example configuration, with all defaults filled in.
+pub fn main_config() -> MainConfig {
+    MainConfig::parse(
+        ConfigPrefix::Service,
+        &PathBuf::from("../config/minimal-config-example.toml"),
+    )
+    .unwrap()
+}
+
+pub fn service_config() -> ServiceConfig {
+    main_config().service
+}
+
+build_info::build_info!(fn build_info);
+
+/// Service state talking to a (mock) graph-node at `graph_node_url`.
+///
+/// The database pool connects lazily, so tests that don't touch the database
+/// don't need one.
+pub async fn subgraph_service_state(graph_node_url: &str) -> SubgraphServiceState {
+    let config = main_config();
+    SubgraphServiceState {
+        error_cooldown: upstream::ErrorCooldown::new(&config.service.upstream),
+        circuit_breaker: upstream::CircuitBreaker::new(&config.service.upstream),
+        status_cache: StatusCache::new(
+            Duration::from_secs(config.service.status.cache_ttl_secs),
+            Duration::from_millis(config.service.status.cache_refresh_ahead_ms),
+            config.service.status.cache_max_entries,
+            config.service.status.max_concurrent_cache_refreshes,
+        ),
+        status_root_fields: routes::supported_root_fields(&config.service.status.extra_root_fields),
+        persisted_queries: PersistedQueries::new(
+            config.service.query.persisted_queries_max_entries,
+        ),
+        service_config: RwLock::new(Arc::new(config.service.clone())),
+        config_file: PathBuf::from("../config/minimal-config-example.toml"),
+        config: config.into(),
+        database: PgPoolOptions::new()
+            .connect_lazy("postgres://postgres@localhost:5432/postgres")
+            .unwrap(),
+        cost_schema: routes::cost::build_schema().await,
+        cost_model_files: Default::default(),
+        cost_models: Default::default(),
+        graph_node_client: reqwest::Client::new(),
+        request_queue: None,
+        upstream_health: Default::default(),
+        status_log: Default::default(),
+        attestation_overrides: Default::default(),
+        response_transforms: Default::default(),
+        deployment_registry: None,
+        head_tracker: None,
+        release: IndexerServiceRelease::from(build_info()),
+        graph_node_status_url: format!("{graph_node_url}/graphql"),
+        graph_node_query_base_url: graph_node_url.to_string(),
+    }
+}
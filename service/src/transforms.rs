@@ -0,0 +1,227 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Context;
+use indexer_config::ResponseTransform;
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use tracing::debug;
+
+pub const SANITIZED_ERROR_MESSAGE: &str = "Failed to execute query";
+
+/// Replace the messages of the GraphQL errors in a response with a generic
+/// one, logging the original messages.
+pub fn sanitize_errors(response: &mut Value) {
+    let Some(errors) = response.get_mut("errors").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for message in errors
+        .iter_mut()
+        .filter_map(|error| error.get_mut("message"))
+    {
+        debug!(%message, "Sanitized upstream error");
+        *message = Value::String(SANITIZED_ERROR_MESSAGE.to_string());
+    }
+}
+
+/// Set `extensions.<key>` of a GraphQL response, keeping other extensions.
+pub fn insert_extension(response: &mut Value, key: &str, value: Value) {
+    let Some(response) = response.as_object_mut() else {
+        return;
+    };
+    if let Some(extensions) = response
+        .entry("extensions")
+        .or_insert_with(|| Value::Object(Default::default()))
+        .as_object_mut()
+    {
+        extensions.insert(key.to_string(), value);
+    }
+}
+
+enum Action {
+    Remove,
+    Rename(String),
+}
+
+/// Response transform rules from the config, with their JSONPaths compiled.
+#[derive(Default)]
+pub struct ResponseTransforms(Vec<(JsonPath, Action)>);
+
+impl ResponseTransforms {
+    pub fn new(rules: &[ResponseTransform]) -> anyhow::Result<Self> {
+        rules
+            .iter()
+            .map(|rule| {
+                let (path, action) = match rule {
+                    ResponseTransform::Remove { path } => (path, Action::Remove),
+                    ResponseTransform::Rename { path, to } => (path, Action::Rename(to.clone())),
+                };
+                let path = JsonPath::parse(path)
+                    .with_context(|| format!("Invalid response transform path `{path}`"))?;
+                Ok((path, action))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Apply all rules in order.
+    pub fn apply(&self, value: &mut Value) {
+        for (path, action) in &self.0 {
+            let pointers = path
+                .query_located(value)
+                .locations()
+                .map(|location| location.to_json_pointer())
+                .collect::<Vec<_>>();
+
+            // Go backwards, so removing array elements doesn't shift the
+            // indices of elements that are yet to be removed
+            for pointer in pointers.iter().rev() {
+                let Some((parent, key)) = pointer.rsplit_once('/') else {
+                    continue;
+                };
+                let key = key.replace("~1", "/").replace("~0", "~");
+                match (value.pointer_mut(parent), action) {
+                    (Some(Value::Object(object)), Action::Remove) => {
+                        object.remove(&key);
+                    }
+                    (Some(Value::Object(object)), Action::Rename(to)) => {
+                        if let Some(field) = object.remove(&key) {
+                            object.insert(to.clone(), field);
+                        }
+                    }
+                    (Some(Value::Array(array)), Action::Remove) => {
+                        if let Ok(index) = key.parse::<usize>() {
+                            if index < array.len() {
+                                array.remove(index);
+                            }
+                        }
+                    }
+                    // Array elements have no name to change
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn transforms(rules: Vec<ResponseTransform>) -> ResponseTransforms {
+        ResponseTransforms::new(&rules).unwrap()
+    }
+
+    #[test]
+    fn test_rename_rule() {
+        let transforms = transforms(vec![ResponseTransform::Rename {
+            path: "$.data.indexingStatuses[*].subgraph".to_string(),
+            to: "deployment".to_string(),
+        }]);
+        let mut value = json!({
+            "data": {
+                "indexingStatuses": [
+                    { "subgraph": "QmA", "health": "healthy" },
+                    { "subgraph": "QmB", "health": "failed" }
+                ]
+            }
+        });
+        transforms.apply(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "data": {
+                    "indexingStatuses": [
+                        { "deployment": "QmA", "health": "healthy" },
+                        { "deployment": "QmB", "health": "failed" }
+                    ]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_remove_rule() {
+        let transforms = transforms(vec![
+            ResponseTransform::Remove {
+                path: "$.data.indexingStatuses[*].chains".to_string(),
+            },
+            ResponseTransform::Remove {
+                path: "$.data.indexingStatuses[?@.health == 'failed']".to_string(),
+            },
+        ]);
+        let mut value = json!({
+            "data": {
+                "indexingStatuses": [
+                    { "subgraph": "QmA", "health": "failed", "chains": [] },
+                    { "subgraph": "QmB", "health": "healthy", "chains": [] },
+                    { "subgraph": "QmC", "health": "failed", "chains": [] }
+                ]
+            }
+        });
+        transforms.apply(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "data": {
+                    "indexingStatuses": [{ "subgraph": "QmB", "health": "healthy" }]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_path() {
+        assert!(ResponseTransforms::new(&[ResponseTransform::Remove {
+            path: "data.a".to_string()
+        }])
+        .is_err());
+    }
+
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_sanitize_errors() {
+        let logs = CapturedLogs::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer({
+                let logs = logs.clone();
+                move || logs.clone()
+            })
+            .finish();
+
+        let mut response = json!({
+            "data": null,
+            "errors": [{ "message": "store error: connection to db-primary:5432 refused" }]
+        });
+        tracing::subscriber::with_default(subscriber, || sanitize_errors(&mut response));
+
+        assert_eq!(
+            response,
+            json!({ "data": null, "errors": [{ "message": SANITIZED_ERROR_MESSAGE }] })
+        );
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("connection to db-primary:5432 refused"));
+    }
+}
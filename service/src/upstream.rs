@@ -0,0 +1,877 @@
+// Copyright 2023-, GraphOps and Semiotic Labs.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::http::{HeaderMap, HeaderName};
+use hmac::{Hmac, Mac};
+use indexer_config::ServiceUpstreamConfig;
+use rand::Rng;
+use reqwest::{NoProxy, Proxy};
+use semver::{Version, VersionReq};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use thegraph::types::DeploymentId;
+use tracing::{debug, info, warn};
+
+/// Build the HTTP client used to forward requests to graph-node.
+pub fn build_client(config: &ServiceUpstreamConfig) -> Result<reqwest::Client, anyhow::Error> {
+    let no_proxy = config.no_proxy.as_deref().and_then(NoProxy::from_string);
+
+    // Adding any proxy disables the proxies picked up from the environment
+    let mut builder = reqwest::ClientBuilder::new()
+        .tcp_nodelay(true)
+        .timeout(Duration::from_secs(config.timeout_secs))
+        // Sends `Accept-Encoding` and transparently decompresses responses
+        .gzip(config.compress)
+        .brotli(config.compress)
+        .deflate(config.compress)
+        .pool_idle_timeout(
+            (config.pool_idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(config.pool_idle_timeout_secs)),
+        );
+    if let Some(max_idle) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max_idle);
+    }
+    if config.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    if let Some(proxy) = &config.http_proxy {
+        builder = builder.proxy(Proxy::http(proxy.as_str())?.no_proxy(no_proxy.clone()));
+    }
+    if let Some(proxy) = &config.https_proxy {
+        builder = builder.proxy(Proxy::https(proxy.as_str())?.no_proxy(no_proxy));
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Headers that only concern a single connection, or are set for each
+/// forwarded request, and are therefore never forwarded.
+const UNFORWARDABLE_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "content-type",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+/// The client headers listed in `forward_headers` that should be passed on to
+/// graph-node. Headers that can't be forwarded or are listed in
+/// `strip_forwarded_headers` are skipped even if they are listed, and so are
+/// headers that would take the forwarded headers beyond
+/// `max_forwarded_header_bytes`.
+pub fn forwarded_headers(config: &ServiceUpstreamConfig, headers: &HeaderMap) -> HeaderMap {
+    let mut forwarded = HeaderMap::new();
+    let mut bytes = 0;
+    for name in &config.forward_headers {
+        let Ok(name) = HeaderName::from_bytes(name.to_lowercase().as_bytes()) else {
+            continue;
+        };
+        if UNFORWARDABLE_HEADERS.contains(&name.as_str())
+            || config
+                .strip_forwarded_headers
+                .iter()
+                .any(|stripped| stripped.eq_ignore_ascii_case(name.as_str()))
+        {
+            continue;
+        }
+        for value in headers.get_all(&name) {
+            let size = name.as_str().len() + value.len();
+            if bytes + size > config.max_forwarded_header_bytes {
+                debug!(header = %name, size, "Not forwarding header beyond the size limit");
+                continue;
+            }
+            bytes += size;
+            forwarded.append(name.clone(), value.clone());
+        }
+    }
+    forwarded
+}
+
+/// Header carrying the signature of a forwarded request body.
+pub const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Hex-encoded HMAC-SHA256 of a request body, for upstreams that authenticate
+/// requests by their signature.
+pub fn sign_body(key: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Run a query against graph-node's status API and make sure it returns data
+/// without errors, with the `expected` values at the given JSON pointers.
+pub async fn validate_upstream(
+    client: &reqwest::Client,
+    status_url: &str,
+    query: &str,
+    expected: &HashMap<String, Value>,
+) -> Result<(), anyhow::Error> {
+    let response: Value = client
+        .post(status_url)
+        .json(&json!({ "query": query }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    if let Some(errors) = response.get("errors") {
+        return Err(anyhow!("Validation query returned errors: {errors}"));
+    }
+    let data = match response.get("data") {
+        Some(Value::Null) | None => return Err(anyhow!("Validation query returned no data")),
+        Some(data) => data,
+    };
+    for (pointer, expected) in expected {
+        match data.pointer(pointer) {
+            Some(value) if value == expected => {}
+            value => {
+                return Err(anyhow!(
+                    "Validation query returned {} at `{pointer}`, expected {expected}",
+                    value.unwrap_or(&Value::Null)
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Ask graph-node's status API for its version and make sure it satisfies
+/// `required`.
+pub async fn check_version(
+    client: &reqwest::Client,
+    status_url: &str,
+    required: &VersionReq,
+) -> Result<Version, anyhow::Error> {
+    let response: Value = client
+        .post(status_url)
+        .json(&json!({ "query": "{ version { version } }" }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let version = response
+        .pointer("/data/version/version")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("Graph node did not report its version: {response}"))?;
+    let version = Version::parse(version.trim_start_matches('v'))
+        .map_err(|e| anyhow!("Graph node reported an invalid version `{version}`: {e}"))?;
+    if !required.matches(&version) {
+        return Err(anyhow!(
+            "Graph node version {version} does not satisfy `{required}`"
+        ));
+    }
+    Ok(version)
+}
+
+/// Timeout for queries to a deployment, which may be overridden per deployment.
+pub fn query_timeout(config: &ServiceUpstreamConfig, deployment: &DeploymentId) -> Duration {
+    Duration::from_secs(
+        config
+            .deployment_timeouts
+            .get(deployment)
+            .copied()
+            .unwrap_or(config.timeout_secs),
+    )
+}
+
+/// Timeout for a status query selecting the given root fields. Status queries
+/// are forwarded as a whole, so the longest timeout among the fields applies.
+pub fn status_timeout<'a>(
+    config: &ServiceUpstreamConfig,
+    root_fields: impl IntoIterator<Item = &'a str>,
+) -> Duration {
+    let default = config.status_timeout_secs.unwrap_or(config.timeout_secs);
+    Duration::from_secs(
+        root_fields
+            .into_iter()
+            .map(|field| {
+                config
+                    .status_field_timeouts
+                    .get(field)
+                    .copied()
+                    .unwrap_or(default)
+            })
+            .max()
+            .unwrap_or(default),
+    )
+}
+
+/// Run an upstream request, retrying it up to `max_retries` times if it fails.
+///
+/// Only use this for requests that are safe to send more than once.
+pub async fn with_retries<T, E, F, Fut>(max_retries: u32, mut request: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    let mut retries = 0;
+    loop {
+        match request().await {
+            Err(e) if retries < max_retries => {
+                retries += 1;
+                warn!(error = %e, retries, max_retries, "Upstream request failed, retrying");
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Forward a request, retrying it up to `max_retries` times with exponential
+/// backoff if graph-node couldn't be reached or answered with a server error.
+///
+/// Other failures aren't retried, as the request may have been received
+/// already, and neither are client errors.
+pub async fn with_backoff<F, Fut>(
+    max_retries: u32,
+    base_delay: Duration,
+    mut request: F,
+) -> Result<reqwest::Response, reqwest::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<reqwest::Response, reqwest::Error>>,
+{
+    let mut retries = 0;
+    loop {
+        let result = request().await;
+        let retryable = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(e) => e.is_connect(),
+        };
+        if !retryable || retries >= max_retries {
+            return result;
+        }
+
+        let delay = backoff_delay(base_delay, retries);
+        retries += 1;
+        let error = match &result {
+            Ok(response) => response.status().to_string(),
+            Err(e) => e.to_string(),
+        };
+        warn!(
+            %error,
+            retries,
+            max_retries,
+            ?delay,
+            "Upstream request failed, retrying"
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Exponential backoff with jitter: `base * 2^retry`, scaled by a random
+/// factor between 0.5 and 1 so retries of concurrent requests spread out.
+fn backoff_delay(base: Duration, retry: u32) -> Duration {
+    base.saturating_mul(1 << retry.min(16))
+        .mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+/// Holds back upstream requests for a while after a burst of errors, to give
+/// graph-node some room to recover.
+pub struct ErrorCooldown {
+    threshold: Option<usize>,
+    window: Duration,
+    cooldown: Duration,
+    errors: Mutex<VecDeque<Instant>>,
+}
+
+impl ErrorCooldown {
+    pub fn new(config: &ServiceUpstreamConfig) -> Self {
+        Self {
+            threshold: config.error_cooldown_threshold.map(|t| t as usize),
+            window: Duration::from_millis(config.error_cooldown_window_ms),
+            cooldown: Duration::from_millis(config.error_cooldown_ms),
+            errors: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn record_error(&self) {
+        if self.threshold.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        let mut errors = self.errors.lock().unwrap();
+        errors.push_back(now);
+        while errors
+            .front()
+            .is_some_and(|error| now.duration_since(*error) > self.window)
+        {
+            errors.pop_front();
+        }
+    }
+
+    /// How long to wait before the next upstream request, if at all.
+    pub fn delay(&self) -> Option<Duration> {
+        let threshold = self.threshold?;
+        let errors = self.errors.lock().unwrap();
+        let last_error = errors.back()?;
+        let first_error = errors.get(errors.len().checked_sub(threshold)?)?;
+        if last_error.duration_since(*first_error) > self.window {
+            return None;
+        }
+        self.cooldown.checked_sub(last_error.elapsed())
+    }
+
+    pub async fn wait(&self) {
+        if let Some(delay) = self.delay() {
+            debug!(?delay, "Upstream error cooldown, delaying request");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Fails upstream requests right away while graph-node seems to be down,
+/// instead of letting each of them run into the timeout.
+///
+/// The circuit opens after `threshold` consecutive failures, as long as they
+/// span at least `min_failure_span`, so that a short burst of failures doesn't
+/// open it. Once the
+/// cooldown has passed, it lets a single trial request through (half-open),
+/// closing again if that succeeds.
+pub struct CircuitBreaker {
+    threshold: Option<u32>,
+    cooldown: Duration,
+    min_failure_span: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    first_failure: Option<Instant>,
+    circuit: Circuit,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum Circuit {
+    #[default]
+    Closed,
+    Open(Instant),
+    HalfOpen(Instant),
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &ServiceUpstreamConfig) -> Self {
+        Self {
+            threshold: config.circuit_breaker_threshold,
+            cooldown: Duration::from_secs(config.circuit_breaker_cooldown_secs),
+            min_failure_span: Duration::from_millis(config.circuit_min_open_duration_ms),
+            state: Default::default(),
+        }
+    }
+
+    /// Whether a request may be sent to graph-node now.
+    pub fn allow_request(&self) -> bool {
+        if self.threshold.is_none() {
+            return true;
+        }
+        let mut state = self.state.lock().unwrap();
+        match state.circuit {
+            Circuit::Closed => true,
+            // Trial requests that never report back don't keep the circuit
+            // half-open forever
+            Circuit::Open(since) | Circuit::HalfOpen(since) if since.elapsed() >= self.cooldown => {
+                info!("Upstream circuit breaker half-open, sending a trial request");
+                state.circuit = Circuit::HalfOpen(Instant::now());
+                true
+            }
+            Circuit::Open(_) | Circuit::HalfOpen(_) => false,
+        }
+    }
+
+    pub fn record_success(&self) {
+        if self.threshold.is_none() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.circuit != Circuit::Closed {
+            info!("Upstream circuit breaker closed");
+        }
+        *state = BreakerState::default();
+    }
+
+    pub fn record_failure(&self) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        let first_failure = *state.first_failure.get_or_insert_with(Instant::now);
+        let trip = match state.circuit {
+            Circuit::Closed => {
+                state.consecutive_failures >= threshold
+                    && first_failure.elapsed() >= self.min_failure_span
+            }
+            Circuit::HalfOpen(_) => true,
+            Circuit::Open(_) => false,
+        };
+        if trip {
+            warn!(
+                consecutive_failures = state.consecutive_failures,
+                cooldown = ?self.cooldown,
+                "Upstream circuit breaker open, failing requests until the cooldown has passed"
+            );
+            state.circuit = Circuit::Open(Instant::now());
+        }
+    }
+}
+
+/// Outcome of the most recent request to graph-node, used to tell whether
+/// the service is ready to serve.
+#[derive(Default)]
+pub struct UpstreamHealth {
+    last_request: Mutex<Option<(Instant, Result<(), String>)>>,
+}
+
+impl UpstreamHealth {
+    pub fn record_success(&self) {
+        *self.last_request.lock().unwrap() = Some((Instant::now(), Ok(())));
+    }
+
+    pub fn record_failure(&self, error: impl Display) {
+        *self.last_request.lock().unwrap() = Some((Instant::now(), Err(error.to_string())));
+    }
+
+    /// The error of the most recent request, if it failed within `window`.
+    /// Without recent requests, graph-node is assumed to be healthy.
+    pub fn recent_failure(&self, window: Duration) -> Option<String> {
+        match &*self.last_request.lock().unwrap() {
+            Some((at, Err(error))) if at.elapsed() <= window => Some(error.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use axum::http::HeaderValue;
+
+    use crate::test_utils;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_go_through_proxy() {
+        let proxy = MockServer::start().await;
+        proxy
+            .register(
+                Mock::given(method("POST"))
+                    .and(path("/subgraphs/id/QmTest"))
+                    .respond_with(ResponseTemplate::new(200).set_body_string("proxied"))
+                    .expect(1),
+            )
+            .await;
+
+        let mut config = test_utils::service_config().upstream;
+        config.http_proxy = Some(proxy.uri().parse().unwrap());
+        let client = build_client(&config).unwrap();
+
+        // The host doesn't exist, so this can only succeed via the proxy
+        let response = client
+            .post("http://graph-node.invalid/subgraphs/id/QmTest")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.text().await.unwrap(), "proxied");
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_after_success() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<u32, &str> =
+            with_retries(3, || async { Ok(attempts.fetch_add(1, Ordering::SeqCst)) }).await;
+        assert_eq!(result, Ok(0));
+        assert_eq!(attempts.into_inner(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_error_cooldown_delays_after_burst() {
+        let mut config = test_utils::service_config().upstream;
+        config.error_cooldown_threshold = Some(2);
+        config.error_cooldown_window_ms = 60_000;
+        config.error_cooldown_ms = 200;
+        let cooldown = ErrorCooldown::new(&config);
+
+        // A single error doesn't trigger the cooldown
+        cooldown.record_error();
+        assert!(cooldown.delay().is_none());
+
+        cooldown.record_error();
+        let start = Instant::now();
+        cooldown.wait().await;
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        // Once the cooldown has passed, requests go through immediately
+        assert!(cooldown.delay().is_none());
+    }
+
+    fn circuit_breaker(threshold: Option<u32>, cooldown_secs: u64) -> CircuitBreaker {
+        let mut config = test_utils::service_config().upstream;
+        config.circuit_breaker_threshold = threshold;
+        config.circuit_breaker_cooldown_secs = cooldown_secs;
+        CircuitBreaker::new(&config)
+    }
+
+    #[test]
+    fn test_circuit_breaker_opens_after_consecutive_failures() {
+        let breaker = circuit_breaker(Some(3), 60);
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.allow_request());
+
+        breaker.record_failure();
+        assert!(!breaker.allow_request());
+    }
+
+    #[test]
+    fn test_circuit_breaker_ignores_short_failure_bursts() {
+        let mut config = test_utils::service_config().upstream;
+        config.circuit_breaker_threshold = Some(2);
+        config.circuit_min_open_duration_ms = 100;
+        let breaker = CircuitBreaker::new(&config);
+
+        for _ in 0..10 {
+            breaker.record_failure();
+        }
+        assert!(breaker.allow_request());
+        assert_eq!(breaker.state.lock().unwrap().circuit, Circuit::Closed);
+
+        // Failures that keep coming for long enough open it
+        std::thread::sleep(Duration::from_millis(110));
+        breaker.record_failure();
+        assert!(!breaker.allow_request());
+    }
+
+    #[test]
+    fn test_circuit_breaker_half_open() {
+        let breaker = circuit_breaker(Some(1), 0);
+        breaker.record_failure();
+
+        // The cooldown has passed, so a trial request goes through
+        assert!(breaker.allow_request());
+        let circuit = breaker.state.lock().unwrap().circuit;
+        assert!(matches!(circuit, Circuit::HalfOpen(_)));
+
+        // A failed trial opens the circuit again
+        breaker.record_failure();
+        let circuit = breaker.state.lock().unwrap().circuit;
+        assert!(matches!(circuit, Circuit::Open(_)));
+
+        assert!(breaker.allow_request());
+        breaker.record_success();
+        assert_eq!(breaker.state.lock().unwrap().circuit, Circuit::Closed);
+    }
+
+    #[test]
+    fn test_circuit_breaker_disabled() {
+        let breaker = circuit_breaker(None, 60);
+        for _ in 0..100 {
+            breaker.record_failure();
+        }
+        assert!(breaker.allow_request());
+    }
+
+    #[test]
+    fn test_error_cooldown_disabled() {
+        let mut config = test_utils::service_config().upstream;
+        config.error_cooldown_threshold = None;
+        let cooldown = ErrorCooldown::new(&config);
+
+        for _ in 0..10 {
+            cooldown.record_error();
+        }
+        assert!(cooldown.delay().is_none());
+    }
+
+    async fn count_forwards(status: u16, max_retries: u32) -> (u16, usize) {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .respond_with(ResponseTemplate::new(status))
+                    .up_to_n_times(1),
+            )
+            .await;
+        graph_node
+            .register(Mock::given(method("POST")).respond_with(ResponseTemplate::new(200)))
+            .await;
+
+        let client = reqwest::Client::new();
+        let response = with_backoff(max_retries, Duration::from_millis(1), || {
+            client.post(graph_node.uri()).send()
+        })
+        .await
+        .unwrap();
+        let requests = graph_node.received_requests().await.unwrap().len();
+        (response.status().as_u16(), requests)
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried() {
+        assert_eq!(count_forwards(503, 2).await, (200, 2));
+        // Without retries, the error is returned
+        assert_eq!(count_forwards(503, 0).await, (503, 1));
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        assert_eq!(count_forwards(400, 2).await, (400, 1));
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        let base = Duration::from_millis(100);
+        for retry in 0..4 {
+            let delay = backoff_delay(base, retry);
+            let max = base * 2u32.pow(retry);
+            assert!(max / 2 <= delay && delay <= max, "{retry}: {delay:?}");
+        }
+    }
+
+    #[test]
+    fn test_sign_body() {
+        // Test case 2 of RFC 4231
+        assert_eq!(
+            sign_body("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_status_timeout_per_field() {
+        let mut config = test_utils::service_config().upstream;
+        config.timeout_secs = 30;
+        config.status_field_timeouts = HashMap::from([
+            ("publicProofsOfIndexing".to_string(), 120),
+            ("chains".to_string(), 5),
+        ]);
+
+        let timeout = |fields: &[&str]| status_timeout(&config, fields.iter().copied());
+        assert_eq!(timeout(&[]), Duration::from_secs(30));
+        assert_eq!(timeout(&["indexingStatuses"]), Duration::from_secs(30));
+        assert_eq!(timeout(&["chains"]), Duration::from_secs(5));
+        assert_eq!(
+            timeout(&["chains", "publicProofsOfIndexing"]),
+            Duration::from_secs(120)
+        );
+        // Fields without an override keep the default timeout
+        assert_eq!(timeout(&["chains", "latestBlock"]), Duration::from_secs(30));
+
+        config.status_timeout_secs = Some(10);
+        assert_eq!(
+            status_timeout(&config, ["latestBlock"]),
+            Duration::from_secs(10)
+        );
+        assert_eq!(status_timeout(&config, ["chains"]), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_upstream_health() {
+        let health = UpstreamHealth::default();
+        let window = Duration::from_secs(60);
+        assert!(health.recent_failure(window).is_none());
+
+        health.record_failure("connection refused");
+        assert_eq!(
+            health.recent_failure(window).as_deref(),
+            Some("connection refused")
+        );
+        // Failures outside of the window don't count
+        assert!(health.recent_failure(Duration::ZERO).is_none());
+
+        health.record_success();
+        assert!(health.recent_failure(window).is_none());
+    }
+
+    async fn validate(response: ResponseTemplate) -> Result<(), anyhow::Error> {
+        validate_expecting(response, HashMap::new()).await
+    }
+
+    async fn validate_expecting(
+        response: ResponseTemplate,
+        expected: HashMap<String, Value>,
+    ) -> Result<(), anyhow::Error> {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path("/graphql"))
+                    .respond_with(response),
+            )
+            .await;
+        validate_upstream(
+            &reqwest::Client::new(),
+            &format!("{}/graphql", graph_node.uri()),
+            "{ indexingStatuses { subgraph } }",
+            &expected,
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_matching_validation_response() {
+        let response =
+            ResponseTemplate::new(200).set_body_json(json!({ "data": { "indexingStatuses": [] } }));
+        assert!(validate(response).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_mismatching_validation_response() {
+        let errors = ResponseTemplate::new(200)
+            .set_body_json(json!({ "errors": [{ "message": "Unknown field" }] }));
+        assert!(validate(errors).await.is_err());
+
+        let no_data = ResponseTemplate::new(200).set_body_json(json!({ "data": null }));
+        assert!(validate(no_data).await.is_err());
+
+        assert!(validate(ResponseTemplate::new(500)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validation_response_is_compared_to_expected_values() {
+        let response = || {
+            ResponseTemplate::new(200).set_body_json(json!({
+                "data": { "indexingStatuses": [{ "subgraph": "Qm1", "health": "healthy" }] }
+            }))
+        };
+        let expect = |pointer: &str, value: Value| HashMap::from([(pointer.to_string(), value)]);
+
+        assert!(validate_expecting(
+            response(),
+            expect("/indexingStatuses/0/health", json!("healthy"))
+        )
+        .await
+        .is_ok());
+        // A different value
+        assert!(validate_expecting(
+            response(),
+            expect("/indexingStatuses/0/health", json!("failed"))
+        )
+        .await
+        .is_err());
+        // A missing value
+        assert!(validate_expecting(
+            response(),
+            expect("/indexingStatuses/1/health", json!("healthy"))
+        )
+        .await
+        .is_err());
+    }
+
+    async fn check(version: Value, required: &str) -> Result<Version, anyhow::Error> {
+        let graph_node = MockServer::start().await;
+        graph_node
+            .register(
+                Mock::given(method("POST"))
+                    .and(path("/graphql"))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .set_body_json(json!({ "data": { "version": version } })),
+                    ),
+            )
+            .await;
+        check_version(
+            &reqwest::Client::new(),
+            &format!("{}/graphql", graph_node.uri()),
+            &VersionReq::parse(required).unwrap(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_matching_graph_node_version() {
+        let version = check(json!({ "version": "0.35.1" }), ">=0.35, <0.37")
+            .await
+            .unwrap();
+        assert_eq!(version, Version::new(0, 35, 1));
+
+        // Tags are often prefixed with a `v`
+        assert!(check(json!({ "version": "v0.36.0" }), ">=0.35, <0.37")
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_mismatching_graph_node_version() {
+        let error = check(json!({ "version": "0.34.1" }), ">=0.35, <0.37")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("0.34.1"));
+
+        assert!(check(json!({ "version": "0.37.0" }), ">=0.35, <0.37")
+            .await
+            .is_err());
+        assert!(check(json!({ "version": "unknown" }), ">=0.35")
+            .await
+            .is_err());
+        assert!(check(Value::Null, ">=0.35").await.is_err());
+    }
+
+    #[test]
+    fn test_forwarded_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", HeaderValue::from_static("00-abc-def-01"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer token"));
+        headers.insert("host", HeaderValue::from_static("indexer.example.com"));
+        headers.insert("x-other", HeaderValue::from_static("other"));
+
+        let mut config = test_utils::service_config().upstream;
+        config.forward_headers = ["TraceParent", "tracestate", "host"]
+            .map(String::from)
+            .into();
+        let forwarded = forwarded_headers(&config, &headers);
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded["traceparent"], "00-abc-def-01");
+
+        // Auth headers are only forwarded when listed, and not stripped
+        config.forward_headers = vec!["authorization".to_string()];
+        assert!(forwarded_headers(&config, &headers).is_empty());
+        config.strip_forwarded_headers = vec![];
+        let forwarded = forwarded_headers(&config, &headers);
+        assert_eq!(forwarded["authorization"], "Bearer token");
+    }
+
+    #[test]
+    fn test_forwarded_headers_size_limit() {
+        let mut headers = HeaderMap::new();
+        headers.insert("traceparent", HeaderValue::from_static("00-abc-def-01"));
+        headers.insert("tracestate", HeaderValue::from_static("vendor=value"));
+
+        let mut config = test_utils::service_config().upstream;
+        config.max_forwarded_header_bytes = "traceparent".len() + "00-abc-def-01".len();
+        let forwarded = forwarded_headers(&config, &headers);
+        assert_eq!(forwarded.len(), 1);
+        assert_eq!(forwarded["traceparent"], "00-abc-def-01");
+    }
+}